@@ -0,0 +1,58 @@
+//! Compares the allocations of fanning out one payload to many subscribers through their outboxes,
+//! encoding a copy of the payload per client as before versus writing the frame shared by every client.
+//! Both runs include the same outbox setup, the difference between them is the per-client cost.
+//! Run with `cargo bench --bench pubsub_fanout --features bench`
+
+use bitwyre_ws_core::{fan_out_frames, BroadcastMessage, MessagePayload};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const SUBSCRIBERS: usize = 10_000;
+const PAYLOAD_SIZE: usize = 50 * 1024;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn measure(name: &str, message: &BroadcastMessage, copy_payload: bool) {
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let stopwatch = Instant::now();
+    let frames = fan_out_frames(SUBSCRIBERS, message, copy_payload);
+    let elapsed = stopwatch.elapsed();
+    println!(
+        "{:<24} {:>8} allocations {:>12} bytes {:>10.3} ms ({} frames)",
+        name,
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations_before,
+        ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes_before,
+        elapsed.as_secs_f64() * 1000.0,
+        frames.len()
+    );
+}
+
+fn main() {
+    let message = BroadcastMessage::from(MessagePayload::from("x".repeat(PAYLOAD_SIZE)));
+    println!(
+        "Fan-out of a {} bytes payload to {} subscribers",
+        PAYLOAD_SIZE, SUBSCRIBERS
+    );
+    measure("copied per client", &message, true);
+    measure("shared frame", &message, false);
+}
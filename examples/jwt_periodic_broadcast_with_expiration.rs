@@ -23,6 +23,7 @@ static GLOBAL: bitwyre_ws_core::mimalloc::MiMalloc = bitwyre_ws_core::mimalloc::
 
 use bitwyre_ws_core::{init_log, jwt, run_periodic_websocket_service};
 use bitwyre_ws_core::{AuthMode, AuthHeader, PeriodicWebsocketConfig, PeriodicWebsocketState};
+use bitwyre_ws_core::{PeriodicMessageGetter, RateLimit, RateLimitAction};
 use once_cell::sync::Lazy;
 use std::{io, sync::Arc, time::Duration};
 
@@ -34,8 +35,17 @@ fn main() -> io::Result<()> {
             binding_path: "/ws/love".into(),
             max_clients: 16384,
             periodic_interval: Duration::from_millis(1000),
-            rapid_request_limit: Duration::from_millis(1000),
-            periodic_message_getter: Arc::new(&|| "love".into()),
+            align_to_wall_clock: false,
+            min_client_interval: Duration::from_millis(1000),
+            max_client_interval: Duration::from_secs(10),
+            delta_updates: None,
+            rate_limit: Some(RateLimit {
+                burst: 1,
+                sustained_per_second: 1.0,
+                action: RateLimitAction::Disconnect { max_violations: 1 },
+            }),
+            periodic_message_getter: PeriodicMessageGetter::Infallible(Arc::new(&|_| "love".into())),
+            getter_failure_limit: None,
             auth: AuthMode::JWT {
                 auth_header: AuthHeader::default(),
                 signing_secret: include_bytes!("../public_key.der"),
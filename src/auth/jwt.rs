@@ -3,6 +3,7 @@ pub use biscuit::jwa::SignatureAlgorithm;
 use super::{ActixResult, ErrorUnauthorized};
 use crate::info;
 use biscuit::{jws::Secret, Empty, Validation, ValidationOptions, JWT};
+use serde_json::Value;
 
 #[derive(Clone, Default)]
 pub struct ClaimCode {
@@ -15,12 +16,14 @@ impl ClaimCode {
         Self::default()
     }
 
-    pub(crate) fn validate(&self, secret: &[u8], token: &str) -> ActixResult<()> {
-        let token = JWT::<Empty, Empty>::new_encoded(token);
+    /// Returns the decoded claims, registered and private ones alike
+    pub(crate) fn validate(&self, secret: &[u8], token: &str) -> ActixResult<Value> {
+        let token = JWT::<Value, Empty>::new_encoded(token);
         let secret = Secret::PublicKey(secret.to_vec());
 
         let token = token.into_decoded(&secret, SignatureAlgorithm::RS256).map_err(ErrorUnauthorized)?;
-        let claims = &token.payload().map_err(ErrorUnauthorized)?.registered;
+        let claims_set = token.payload().map_err(ErrorUnauthorized)?;
+        let claims = &claims_set.registered;
 
         let is_error = if claims.not_before.is_none() && self.nbf {
             info!("Client connection unauthorized because `nbf` claims code not found");
@@ -47,7 +50,7 @@ impl ClaimCode {
         if let Some(timestamp) = claims.expiry {
             info!("Client connection authorized expire at {}", timestamp.to_rfc3339());
         }
-        Ok(())
+        serde_json::to_value(claims_set).map_err(ErrorUnauthorized)
     }
 }
 
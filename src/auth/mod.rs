@@ -1,6 +1,7 @@
 pub(super) use crate::actix_web::Result as ActixResult;
 use crate::actix_web::{error::ErrorUnauthorized, HttpRequest};
 use actix_web::http::header::HeaderMap;
+use serde_json::Value;
 
 pub mod jwt;
 
@@ -62,16 +63,17 @@ impl AuthMode {
         }
     }
 
-    pub(crate) fn validate(&self, request: &HttpRequest) -> ActixResult<()> {
+    /// Returns the decoded JWT claims, `None` when authentication is disabled
+    pub(crate) fn validate(&self, request: &HttpRequest) -> ActixResult<Option<Value>> {
         match self {
-            Self::None => Ok(()),
+            Self::None => Ok(None),
             Self::JWT {
                 auth_header: template,
                 validate: claim_code,
                 signing_secret: secret,
             } => {
                 let token = extract_token(template, request.headers())?;
-                claim_code.validate(secret, token)
+                claim_code.validate(secret, token).map(Some)
             }
         }
     }
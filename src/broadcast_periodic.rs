@@ -1,8 +1,16 @@
 use crate::actix::Actor as ActixActor;
 use crate::actix::ActorContext;
+use crate::actix::Addr;
+use crate::actix::Arbiter;
 use crate::actix::AsyncContext;
+use crate::actix::Context;
+use crate::actix::Handler;
+use crate::actix::Message;
+use crate::actix::Recipient;
 use crate::actix::Running;
+use crate::actix::SpawnHandle;
 use crate::actix::StreamHandler;
+use crate::actix::System as ActixSystem;
 use crate::actix_web::middleware;
 use crate::actix_web::web;
 use crate::actix_web::web::Data as ActixData;
@@ -12,33 +20,108 @@ use crate::actix_web::Error as HttpError;
 use crate::actix_web::HttpRequest;
 use crate::actix_web::HttpResponse;
 use crate::actix_web::HttpServer as ActixHttpServer;
-use crate::actix_web_actors::ws::start as ws_start;
+use crate::actix_web_actors::ws::CloseCode;
+use crate::actix_web_actors::ws::CloseReason;
 use crate::actix_web_actors::ws::Message as WsMessage;
 use crate::actix_web_actors::ws::ProtocolError as WsProtocolError;
 use crate::actix_web_actors::ws::WebsocketContext;
 use crate::auth::AuthMode;
+use crate::bytes::Bytes;
+use crate::common_types::is_ping;
 use crate::common_types::CommonResponse;
+use crate::common_types::JsonSerializable;
+use crate::common_types::MessagePayload;
 use crate::debug;
+use crate::error;
+use crate::frame_writer::start_with_frames;
+use crate::frame_writer::FrameWriter;
 use crate::futures::future::ok;
 use crate::futures::prelude::*;
+use crate::handler_error::HandlerError;
 use crate::info;
+use crate::periodic_delta::DeltaState;
+use crate::periodic_delta::DeltaTick;
+use crate::periodic_delta::DeltaUpdates;
+use crate::rate_limiter::RateLimit;
+use crate::rate_limiter::RateLimiter;
+use crate::sentry::capture_message;
+use crate::sentry::Level as SentryLevel;
+use crate::service_handle::notify_start_failed;
+use crate::service_handle::start_service;
+use crate::service_handle::ConnectedClients;
+use crate::service_handle::GoingAway;
+use crate::service_handle::ServiceHandle;
+use crate::service_handle::ServiceHandleSlot;
+use crate::service_handle::StartedNotifier;
+use crate::uuid::Uuid;
 use crate::ACTOR_MAILBOX_CAPACITY;
 use crate::NOTFOUND_MESSAGE;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::io::Result as IOResult;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::RwLock;
 use std::time::Duration;
-use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const INVALID_SUBSCRIPTION_MESSAGE: &str = "Invalid subscription";
+const PERIODIC_UNAVAILABLE_MESSAGE: &str = "Periodic data unavailable";
+
+/// Parameters a client sends in its subscription frame, e.g. `{"symbol":"BTC-USD","depth":10}`
+pub type PeriodicParams = Map<String, Value>;
+pub type InfalliblePeriodicGetter = Arc<&'static (dyn Fn(&PeriodicParams) -> MessagePayload + Sync + Send)>;
+pub type FalliblePeriodicGetter =
+    Arc<&'static (dyn Fn(&PeriodicParams) -> Result<Option<MessagePayload>, HandlerError> + Sync + Send)>;
+
+#[derive(Clone)]
+pub enum PeriodicMessageGetter {
+    Infallible(InfalliblePeriodicGetter),
+    /// `None` skips the tick, an error is logged on every failed tick and reported to Sentry once per outage
+    Fallible(FalliblePeriodicGetter),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GetterFailureAction {
+    /// Send a `CommonResponse` error frame, repeated after every further `max_consecutive_failures`
+    ErrorFrame,
+    /// Close with `1011 Internal Error`
+    Disconnect,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GetterFailureLimit {
+    /// Failed updates of a client in a row before the action applies, skipped ticks do not reset the count
+    pub max_consecutive_failures: u32,
+    pub action: GetterFailureAction,
+}
 
 pub struct PeriodicWebsocketConfig {
     pub binding_url: String,
     pub binding_path: String,
     pub max_clients: usize,
+    /// Initial interval, see `PeriodicWebsocketState::set_periodic_interval`
     pub periodic_interval: Duration,
-    pub rapid_request_limit: Duration,
-    pub periodic_message_getter: Arc<&'static (dyn Fn() -> String + Sync + Send)>,
+    /** Ticks fall on multiples of `periodic_interval` since the UNIX epoch, e.g. exactly on each second */
+    pub align_to_wall_clock: bool,
+    /** Bounds of the interval a client may ask for in `{"op":"subscribe","params":{...},"interval_ms":...}`,
+    intervals are rounded up to multiples of `periodic_interval` since every stream runs on the shared ticker.
+    A stream is never updated faster than `min_client_interval`, the subscription reply holds the actual interval */
+    pub min_client_interval: Duration,
+    pub max_client_interval: Duration,
+    /// Sends JSON documents as patches against the last update of each client instead of in full
+    pub delta_updates: Option<DeltaUpdates>,
+    pub rate_limit: Option<RateLimit>,
+    /** Called once per tick for every distinct set of parameters, clients that never subscribed get empty parameters.
+    Can be swapped with `PeriodicWebsocketState::set_periodic_message_getter` */
+    pub periodic_message_getter: PeriodicMessageGetter,
+    pub getter_failure_limit: Option<GetterFailureLimit>,
     pub auth: AuthMode,
 }
 
@@ -46,14 +129,82 @@ pub struct PeriodicWebsocketState {
     pub active_clients: AtomicUsize,
     pub rejection_counter: AtomicUsize,
     pub config: PeriodicWebsocketConfig,
+    periodic_interval: RwLock<Duration>,
+    periodic_message_getter: RwLock<PeriodicMessageGetter>,
+    ticker: RwLock<Option<Addr<PeriodicTickerActor>>>,
+    connected_clients: ConnectedClients,
+    service_handle: ServiceHandleSlot,
 }
 
 pub(crate) struct PeriodicBroadcastActor {
-    last_request_stopwatch: Instant,
-    rapid_request_limit: Duration,
-    periodic_interval: Duration,
+    client_id: Uuid,
+    state: &'static PeriodicWebsocketState,
+    connected_clients: &'static ConnectedClients,
+    rate_limiter: RateLimiter,
+    ticker: Addr<PeriodicTickerActor>,
     client_closed_callback: Box<dyn Fn()>,
-    periodic_message_getter: Arc<&'static (dyn Fn() -> String + Sync + Send)>,
+    frames: FrameWriter,
+}
+
+/** Owns the single timer of the service, the periodic message is computed and encoded once per tick
+and the same frame is fanned out to every connected client */
+pub(crate) struct PeriodicTickerActor {
+    state: &'static PeriodicWebsocketState,
+    clients: HashMap<Uuid, TickerClient>,
+    /// Keys of the params the getter is currently failing for, reported to Sentry once per outage
+    failing_params: HashSet<String>,
+    tick_count: u64,
+    schedule: Option<SpawnHandle>,
+}
+
+struct TickerClient {
+    recipient: Recipient<PeriodicTick>,
+    stream: PeriodicStream,
+    delta: DeltaState,
+    failures: u32,
+}
+
+enum TickPayload {
+    /// The payload with its encoded frame
+    Ready(Arc<MessagePayload>, Bytes),
+    Skipped,
+    Failed(HandlerError),
+}
+
+/// The variant of the feed a client receives, every client starts on the default stream
+pub(crate) struct PeriodicStream {
+    params: PeriodicParams,
+    params_key: String,
+    interval: Option<Duration>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum PeriodicRequest {
+    Subscribe {
+        #[serde(default)]
+        params: PeriodicParams,
+        #[serde(default)]
+        interval_ms: Option<u64>,
+    },
+}
+
+impl JsonSerializable<'_> for PeriodicRequest {}
+
+#[derive(Message)]
+pub(crate) enum PeriodicTick {
+    /// Encoded frame, shared by every client receiving the same update
+    Update(Bytes),
+    /// The getter failed `max_consecutive_failures` times in a row for this client
+    Unavailable(GetterFailureAction),
+}
+
+#[derive(Message)]
+pub(crate) enum TickerSignal {
+    Subscribe(Uuid, Recipient<PeriodicTick>),
+    ChangeStream(Uuid, PeriodicStream),
+    Unsubscribe(Uuid),
+    Reschedule,
 }
 
 impl PeriodicWebsocketState {
@@ -61,21 +212,69 @@ impl PeriodicWebsocketState {
         Self {
             active_clients: AtomicUsize::new(0),
             rejection_counter: AtomicUsize::new(0),
+            periodic_interval: RwLock::new(config.periodic_interval),
+            periodic_message_getter: RwLock::new(config.periodic_message_getter.clone()),
             config,
+            ticker: RwLock::new(None),
+            connected_clients: ConnectedClients::default(),
+            service_handle: ServiceHandleSlot::default(),
+        }
+    }
+
+    /// Available once the server is listening, `start_periodic_websocket_service` also returns it
+    pub fn service_handle(&self) -> Option<ServiceHandle> {
+        self.service_handle.get()
+    }
+
+    pub fn periodic_interval(&self) -> Duration {
+        *self.periodic_interval.read().unwrap()
+    }
+
+    /** Restarts the shared ticker with the new interval, connected clients keep their streams and
+    their requested intervals are rounded against the new one */
+    pub fn set_periodic_interval(&self, periodic_interval: Duration) {
+        *self.periodic_interval.write().unwrap() = periodic_interval;
+        if let Some(ticker) = self.ticker() {
+            ticker.do_send(TickerSignal::Reschedule);
         }
     }
+
+    /// Takes effect from the next tick for every connected client
+    pub fn set_periodic_message_getter(&self, periodic_message_getter: PeriodicMessageGetter) {
+        *self.periodic_message_getter.write().unwrap() = periodic_message_getter;
+    }
+
+    fn periodic_message_getter(&self) -> PeriodicMessageGetter {
+        self.periodic_message_getter.read().unwrap().clone()
+    }
+
+    fn ticker(&self) -> Option<Addr<PeriodicTickerActor>> {
+        self.ticker.read().unwrap().clone()
+    }
 }
 
 impl PeriodicBroadcastActor {
-    fn new(config: &'static PeriodicWebsocketConfig, client_closed_callback: Box<dyn Fn()>) -> Self {
+    fn new(
+        state: &'static PeriodicWebsocketState,
+        ticker: Addr<PeriodicTickerActor>,
+        client_closed_callback: Box<dyn Fn()>,
+        frames: FrameWriter,
+    ) -> Self {
         Self {
-            last_request_stopwatch: Instant::now(),
-            rapid_request_limit: config.rapid_request_limit,
-            periodic_interval: config.periodic_interval,
+            client_id: Uuid::new_v4(),
+            state,
+            connected_clients: &state.connected_clients,
+            rate_limiter: RateLimiter::new(state.config.rate_limit),
+            ticker,
             client_closed_callback,
-            periodic_message_getter: config.periodic_message_getter.clone(),
+            frames,
         }
     }
+
+    /// Replies go through the frame writer as well, so they keep their order with the updates
+    fn reply(&self, text: String) {
+        self.frames.write(MessagePayload::Text(text).encode_frame());
+    }
 }
 
 impl ActixActor for PeriodicBroadcastActor {
@@ -83,31 +282,70 @@ impl ActixActor for PeriodicBroadcastActor {
 
     fn started(&mut self, context: &mut Self::Context) {
         context.set_mailbox_capacity(ACTOR_MAILBOX_CAPACITY);
-        self.start_periodic_broadcast(context);
+        self.connected_clients
+            .register(self.client_id, context.address().recipient());
+        self.ticker
+            .do_send(TickerSignal::Subscribe(self.client_id, context.address().recipient()));
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.frames.close();
+        self.connected_clients.unregister(&self.client_id);
+        self.ticker.do_send(TickerSignal::Unsubscribe(self.client_id));
         (*self.client_closed_callback)();
         Running::Stop
     }
 }
 
+impl Handler<GoingAway> for PeriodicBroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, _: GoingAway, context: &mut Self::Context) {
+        context.close(Some(CloseCode::Away.into()));
+        context.stop();
+    }
+}
+
+impl Handler<PeriodicTick> for PeriodicBroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, tick: PeriodicTick, context: &mut Self::Context) {
+        match tick {
+            PeriodicTick::Update(frame) => self.frames.write(frame),
+            PeriodicTick::Unavailable(GetterFailureAction::ErrorFrame) => {
+                let mut response = CommonResponse::default();
+                response.error.push(PERIODIC_UNAVAILABLE_MESSAGE.to_owned());
+                self.reply(response.to_json());
+            }
+            PeriodicTick::Unavailable(GetterFailureAction::Disconnect) => {
+                context.close(Some(CloseReason {
+                    code: CloseCode::Error,
+                    description: Some(PERIODIC_UNAVAILABLE_MESSAGE.to_owned()),
+                }));
+                context.stop();
+            }
+        }
+    }
+}
+
 impl StreamHandler<WsMessage, WsProtocolError> for PeriodicBroadcastActor {
     fn handle(&mut self, payload: WsMessage, context: &mut Self::Context) {
-        if self.last_request_stopwatch.elapsed() < self.rapid_request_limit {
+        if let WsMessage::Close(_) = payload {
             context.stop();
             return;
         }
-        self.last_request_stopwatch = Instant::now();
+        if !self.rate_limiter.admit(context) {
+            return;
+        }
         match payload {
-            WsMessage::Close(_) => context.stop(),
             WsMessage::Ping(ping_payload) => context.pong(&ping_payload),
             WsMessage::Text(text) => {
-                if text.len() < 4 {
+                if text.starts_with('{') {
+                    self.handle_client_request(&text);
                     return;
                 }
-                if let "ping" = &text.to_lowercase()[0..4] {
-                    context.text("pong")
+                if is_ping(&text) {
+                    self.reply("pong".to_owned())
                 }
             }
             _ => (),
@@ -116,12 +354,274 @@ impl StreamHandler<WsMessage, WsProtocolError> for PeriodicBroadcastActor {
 }
 
 impl PeriodicBroadcastActor {
-    fn start_periodic_broadcast(&self, context: &mut <Self as ActixActor>::Context) {
-        let tick_handler = self.periodic_message_getter.clone();
-        context.run_interval(self.periodic_interval, move |_, ctx| {
-            ctx.text(tick_handler());
+    fn handle_client_request(&mut self, text: &str) {
+        let mut response = CommonResponse::default();
+        match PeriodicRequest::from_json(text) {
+            Some(PeriodicRequest::Subscribe { params, interval_ms }) => {
+                let PeriodicWebsocketConfig {
+                    min_client_interval,
+                    max_client_interval,
+                    ..
+                } = self.state.config;
+                let periodic_interval = self.state.periodic_interval();
+                let interval = interval_ms.map(|interval_ms| {
+                    Duration::from_millis(interval_ms)
+                        .max(min_client_interval)
+                        .min(max_client_interval)
+                });
+                let ticks = ticks_per_update(interval, periodic_interval, min_client_interval, max_client_interval);
+                let interval_ms = periodic_interval.as_millis() * u128::from(ticks);
+                response.result.insert("op".to_owned(), "subscribe".to_owned());
+                response
+                    .result
+                    .insert("interval_ms".to_owned(), interval_ms.to_string());
+                let stream = PeriodicStream::new(params, interval);
+                self.ticker.do_send(TickerSignal::ChangeStream(self.client_id, stream));
+            }
+            None => response.error.push(INVALID_SUBSCRIPTION_MESSAGE.to_owned()),
+        }
+        self.reply(response.to_json());
+    }
+}
+
+impl PeriodicMessageGetter {
+    fn get(&self, params: &PeriodicParams) -> TickPayload {
+        let getter = match self {
+            Self::Infallible(getter) => return TickPayload::ready(getter(params)),
+            Self::Fallible(getter) => getter,
+        };
+        match getter(params) {
+            Ok(Some(payload)) => TickPayload::ready(payload),
+            Ok(None) => TickPayload::Skipped,
+            Err(getter_error) => {
+                error!("Periodic message getter failed: {}", getter_error);
+                TickPayload::Failed(getter_error)
+            }
+        }
+    }
+}
+
+impl TickPayload {
+    fn ready(payload: MessagePayload) -> Self {
+        let frame = payload.encode_frame();
+        Self::Ready(Arc::new(payload), frame)
+    }
+}
+
+impl PeriodicStream {
+    fn new(params: PeriodicParams, interval: Option<Duration>) -> Self {
+        Self {
+            params_key: serde_json::to_string(&params).unwrap(),
+            params,
+            interval,
+        }
+    }
+}
+
+impl PeriodicTickerActor {
+    fn new(state: &'static PeriodicWebsocketState) -> Self {
+        Self {
+            state,
+            clients: HashMap::with_capacity(state.config.max_clients),
+            failing_params: HashSet::new(),
+            tick_count: 0,
+            schedule: None,
+        }
+    }
+
+    fn schedule(&mut self, context: &mut <Self as ActixActor>::Context) {
+        if let Some(schedule) = self.schedule.take() {
+            context.cancel_future(schedule);
+        }
+        let periodic_interval = self.state.periodic_interval();
+        if !self.state.config.align_to_wall_clock {
+            self.schedule = Some(context.run_interval(periodic_interval, |ticker, _| ticker.tick()));
+            return;
+        }
+        self.schedule_aligned(until_next_tick(since_epoch(), periodic_interval), context);
+    }
+
+    /// Each aligned tick arms the next one from the wall clock, so the ticks stay on the boundaries instead of drifting
+    fn schedule_aligned(&mut self, delay: Duration, context: &mut <Self as ActixActor>::Context) {
+        let aligned_tick = context.run_later(delay, |ticker, context| {
+            let fired_at = since_epoch();
+            ticker.tick();
+            let delay = until_tick_after(fired_at, ticker.state.periodic_interval());
+            ticker.schedule_aligned(delay, context);
         });
+        self.schedule = Some(aligned_tick);
     }
+
+    fn tick(&mut self) {
+        self.tick_count += 1;
+        let PeriodicWebsocketConfig {
+            min_client_interval,
+            max_client_interval,
+            delta_updates,
+            getter_failure_limit,
+            ..
+        } = &self.state.config;
+        let periodic_interval = self.state.periodic_interval();
+        let periodic_message_getter = self.state.periodic_message_getter();
+        // Streams with the same parameters due on this tick share one payload
+        let mut payloads: HashMap<&str, TickPayload> = HashMap::new();
+        let mut delta_tick = DeltaTick::default();
+        for client in self.clients.values_mut() {
+            let TickerClient {
+                recipient,
+                stream,
+                delta,
+                failures,
+            } = client;
+            let ticks = ticks_per_update(
+                stream.interval,
+                periodic_interval,
+                *min_client_interval,
+                *max_client_interval,
+            );
+            if self.tick_count % ticks != 0 {
+                continue;
+            }
+            let payload = payloads
+                .entry(&stream.params_key)
+                .or_insert_with(|| periodic_message_getter.get(&stream.params));
+            let (payload, payload_frame) = match payload {
+                TickPayload::Ready(payload, payload_frame) => (payload, payload_frame),
+                TickPayload::Skipped => continue,
+                TickPayload::Failed(_) => {
+                    *failures += 1;
+                    match getter_failure_limit {
+                        Some(limit) if *failures >= limit.max_consecutive_failures => {
+                            *failures = 0;
+                            let _ = recipient.do_send(PeriodicTick::Unavailable(limit.action));
+                        }
+                        _ => (),
+                    }
+                    continue;
+                }
+            };
+            *failures = 0;
+            let frame = match delta_updates {
+                Some(delta_updates) => delta.next_frame(delta_updates, payload, payload_frame, &mut delta_tick),
+                None => Some(payload_frame.clone()),
+            };
+            if let Some(frame) = frame {
+                let _ = recipient.do_send(PeriodicTick::Update(frame));
+            }
+        }
+        for (params_key, payload) in payloads.iter() {
+            report_getter_transition(&mut self.failing_params, params_key, payload);
+        }
+        if !self.failing_params.is_empty() {
+            let clients = &self.clients;
+            self.failing_params
+                .retain(|params_key| clients.values().any(|client| client.stream.params_key == *params_key));
+        }
+    }
+}
+
+impl ActixActor for PeriodicTickerActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, context: &mut Self::Context) {
+        self.schedule(context);
+    }
+}
+
+impl Handler<TickerSignal> for PeriodicTickerActor {
+    type Result = ();
+
+    fn handle(&mut self, signal: TickerSignal, context: &mut Self::Context) {
+        match signal {
+            TickerSignal::Subscribe(client_id, recipient) => {
+                let stream = PeriodicStream::new(PeriodicParams::new(), None);
+                let client = TickerClient {
+                    recipient,
+                    stream,
+                    delta: DeltaState::default(),
+                    failures: 0,
+                };
+                self.clients.insert(client_id, client);
+            }
+            TickerSignal::ChangeStream(client_id, stream) => {
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.stream = stream;
+                    client.delta = DeltaState::default();
+                }
+            }
+            TickerSignal::Unsubscribe(client_id) => {
+                self.clients.remove(&client_id);
+            }
+            TickerSignal::Reschedule => self.schedule(context),
+        }
+    }
+}
+
+/// Reports the getter to Sentry when it starts failing for a set of params, and when it recovers
+fn report_getter_transition(failing_params: &mut HashSet<String>, params_key: &str, payload: &TickPayload) {
+    match payload {
+        TickPayload::Failed(getter_error) => {
+            if failing_params.insert(params_key.to_owned()) {
+                let message = format!("Periodic message getter failed for {}: {}", params_key, getter_error);
+                capture_message(&message, SentryLevel::Error);
+            }
+        }
+        TickPayload::Ready(..) | TickPayload::Skipped => {
+            if failing_params.remove(params_key) {
+                let message = format!("Periodic message getter recovered for {}", params_key);
+                info!("{}", message);
+                capture_message(&message, SentryLevel::Info);
+            }
+        }
+    }
+}
+
+/** Ticks of the shared timer between two updates of a stream, at least one. Rounds up so the stream is never
+updated faster than asked, and one tick down when that overshoots `max_interval` while still above `min_interval` */
+fn ticks_per_update(
+    interval: Option<Duration>,
+    periodic_interval: Duration,
+    min_interval: Duration,
+    max_interval: Duration,
+) -> u64 {
+    let interval = match interval {
+        Some(interval) => interval.as_nanos(),
+        None => return 1,
+    };
+    let tick_nanos = periodic_interval.as_nanos().max(1);
+    let within_bounds = |ticks: u128| {
+        ticks > 0 && ticks * tick_nanos >= min_interval.as_nanos() && ticks * tick_nanos <= max_interval.as_nanos()
+    };
+    let ticks = ((interval + tick_nanos - 1) / tick_nanos).max(1);
+    if !within_bounds(ticks) && within_bounds(ticks - 1) {
+        (ticks - 1) as u64
+    } else {
+        ticks as u64
+    }
+}
+
+/// Time left until the next multiple of `periodic_interval` since the UNIX epoch
+fn since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Delay from a tick to the following boundary, even when the timer fired a little before or after its own
+fn until_tick_after(fired_at: Duration, periodic_interval: Duration) -> Duration {
+    let half_interval = periodic_interval / 2;
+    until_next_tick(fired_at + half_interval, periodic_interval) + half_interval
+}
+
+fn until_next_tick(since_epoch: Duration, periodic_interval: Duration) -> Duration {
+    let interval_nanos = periodic_interval.as_nanos();
+    if interval_nanos == 0 {
+        return Duration::from_secs(0);
+    }
+    let elapsed_nanos = since_epoch.as_nanos() % interval_nanos;
+    let remaining_nanos = (interval_nanos - elapsed_nanos) % interval_nanos;
+    Duration::new(
+        (remaining_nanos / 1_000_000_000) as u64,
+        (remaining_nanos % 1_000_000_000) as u32,
+    )
 }
 
 fn reject_unmapped_handler(
@@ -148,13 +648,17 @@ fn ws_upgrader(
     request: HttpRequest,
     stream: Payload,
 ) -> Result<HttpResponse, HttpError> {
+    let state: &'static PeriodicWebsocketState = *shared_state.get_ref().as_ref();
     let PeriodicWebsocketState {
         active_clients, config, ..
-    } = shared_state.get_ref().as_ref();
+    } = state;
     config.auth.validate(&request)?;
-    let upgrade_result = ws_start(
+    let ticker = state.ticker().unwrap();
+    let frames = FrameWriter::default();
+    let upgrade_result = start_with_frames(
         PeriodicBroadcastActor::new(
-            &config,
+            state,
+            ticker,
             Box::new(move || {
                 let active_clients = active_clients.fetch_sub(1, Ordering::Relaxed);
                 info!(
@@ -162,7 +666,9 @@ fn ws_upgrader(
                     active_clients - 1
                 );
             }),
+            frames.clone(),
         ),
+        frames,
         &request,
         stream,
     );
@@ -177,14 +683,38 @@ fn ws_upgrader(
 }
 
 pub fn run_periodic_websocket_service(state: Arc<&'static PeriodicWebsocketState>) -> IOResult<()> {
+    serve_periodic_websocket(state, None)
+}
+
+/// Runs the service on a thread of its own, returns once the server is listening
+pub fn start_periodic_websocket_service(state: Arc<&'static PeriodicWebsocketState>) -> IOResult<ServiceHandle> {
+    start_service("periodic-websocket", move |started| {
+        if let Err(run_error) = serve_periodic_websocket(state, Some(started)) {
+            error!("Periodic websocket service failed: {}", run_error);
+        }
+    })
+}
+
+fn serve_periodic_websocket(
+    state: Arc<&'static PeriodicWebsocketState>,
+    started: Option<StartedNotifier>,
+) -> IOResult<()> {
     let PeriodicWebsocketConfig {
         binding_url,
         binding_path,
         max_clients,
         ..
     } = &state.config;
+    let ticker_state: &'static PeriodicWebsocketState = *state;
+    let connected_clients = &state.connected_clients;
+    let service_handle = &state.service_handle;
+    let system = ActixSystem::new("periodic-websocket");
+    let mut ticker_arbiter = Arbiter::new();
+    let ticker =
+        PeriodicTickerActor::start_in_arbiter(&ticker_arbiter, move |_| PeriodicTickerActor::new(ticker_state));
+    *state.ticker.write().unwrap() = Some(ticker);
     let shared_data = ActixData::new(state);
-    ActixHttpServer::new(move || {
+    let bound = ActixHttpServer::new(move || {
         ActixApp::new()
             .register_data(shared_data.clone())
             .wrap(middleware::Logger::default())
@@ -193,7 +723,131 @@ pub fn run_periodic_websocket_service(state: Arc<&'static PeriodicWebsocketState
     })
     .maxconn(*max_clients)
     .shutdown_timeout(1)
-    .bind(binding_url)
-    .unwrap()
-    .run()
+    .system_exit()
+    .bind(binding_url);
+    let server = match bound {
+        Ok(server) => server.start(),
+        Err(bind_error) => {
+            ticker_arbiter.stop();
+            let _ = ticker_arbiter.join();
+            return Err(notify_start_failed(started, bind_error));
+        }
+    };
+    let (stop_notifier, handle) = ServiceHandle::new(server, connected_clients);
+    service_handle.set(handle, started);
+    let run_result = system.run();
+    ticker_arbiter.stop();
+    let _ = ticker_arbiter.join();
+    let _ = stop_notifier.send(());
+    run_result
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_align_to_interval_boundaries() {
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            until_next_tick(Duration::from_millis(10_250), second),
+            Duration::from_millis(750)
+        );
+        assert_eq!(until_next_tick(Duration::from_secs(10), second), Duration::from_secs(0));
+        assert_eq!(
+            until_next_tick(Duration::from_secs(125), Duration::from_secs(60)),
+            Duration::from_secs(55)
+        );
+        assert_eq!(
+            until_next_tick(Duration::from_secs(7), Duration::from_secs(0)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn test_aligned_ticks_rearm_on_the_next_boundary() {
+        let second = Duration::from_secs(1);
+        assert_eq!(until_tick_after(Duration::from_secs(10), second), second);
+        assert_eq!(
+            until_tick_after(Duration::from_millis(9_998), second),
+            Duration::from_millis(1_002)
+        );
+        assert_eq!(
+            until_tick_after(Duration::from_millis(10_003), second),
+            Duration::from_millis(997)
+        );
+    }
+
+    #[test]
+    fn test_client_intervals_round_to_ticks() {
+        let millis = Duration::from_millis;
+        let ticks = |interval: u64, tick: u64, min: u64, max: u64| {
+            ticks_per_update(Some(millis(interval)), millis(tick), millis(min), millis(max))
+        };
+        assert_eq!(ticks_per_update(None, millis(100), millis(200), millis(1000)), 1);
+        assert_eq!(ticks(500, 100, 200, 1000), 5);
+        assert_eq!(ticks(540, 100, 200, 1000), 6);
+        assert_eq!(ticks(10, 100, 0, 1000), 1);
+        assert_eq!(ticks(550, 100, 500, 550), 5);
+        assert_eq!(ticks(450, 300, 400, 500), 2);
+    }
+
+    #[test]
+    fn test_fallible_getter_outcomes() {
+        fn order_book(params: &PeriodicParams) -> Result<Option<MessagePayload>, HandlerError> {
+            match params.get("symbol").and_then(Value::as_str) {
+                Some("BTC-USD") => Ok(Some("book".into())),
+                Some(_) => Ok(None),
+                None => Err(HandlerError::new("cache unavailable")),
+            }
+        }
+        let getter = PeriodicMessageGetter::Fallible(Arc::new(&order_book));
+        let params = |json: &str| serde_json::from_str::<PeriodicParams>(json).unwrap();
+        match getter.get(&params(r#"{"symbol":"BTC-USD"}"#)) {
+            TickPayload::Ready(payload, frame) => {
+                assert_eq!(*payload, MessagePayload::from("book"));
+                assert_eq!(frame, payload.encode_frame());
+            }
+            _ => panic!("Getter result should be sent"),
+        }
+        assert!(match getter.get(&params(r#"{"symbol":"ETH-USD"}"#)) {
+            TickPayload::Skipped => true,
+            _ => false,
+        });
+        assert!(match getter.get(&PeriodicParams::new()) {
+            TickPayload::Failed(getter_error) => getter_error.message() == "cache unavailable",
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_getter_failures_are_tracked_per_params() {
+        let mut failing_params = HashSet::new();
+        let failed = || TickPayload::Failed(HandlerError::new("cache unavailable"));
+        report_getter_transition(&mut failing_params, "{}", &failed());
+        report_getter_transition(&mut failing_params, "{}", &failed());
+        report_getter_transition(&mut failing_params, r#"{"symbol":"BTC-USD"}"#, &TickPayload::Skipped);
+        assert_eq!(failing_params.len(), 1);
+        report_getter_transition(&mut failing_params, "{}", &TickPayload::ready("book".into()));
+        assert!(failing_params.is_empty());
+    }
+
+    #[test]
+    fn test_subscription_frame_parsing() {
+        let frame = r#"{"op":"subscribe","params":{"symbol":"BTC-USD","depth":10},"interval_ms":500}"#;
+        match PeriodicRequest::from_json(frame) {
+            Some(PeriodicRequest::Subscribe { params, interval_ms }) => {
+                assert_eq!(params.get("symbol"), Some(&Value::from("BTC-USD")));
+                assert_eq!(interval_ms, Some(500));
+            }
+            None => panic!("Subscription frame should parse"),
+        }
+        assert!(PeriodicRequest::from_json(r#"{"op":"subscribe"}"#).is_some());
+        assert!(PeriodicRequest::from_json(r#"{"op":"subscribe","params":[1]}"#).is_none());
+        let params = |json: &str| serde_json::from_str::<PeriodicParams>(json).unwrap();
+        assert_eq!(
+            PeriodicStream::new(params(r#"{"b":1,"a":2}"#), None).params_key,
+            PeriodicStream::new(params(r#"{"a":2,"b":1}"#), None).params_key
+        );
+    }
 }
@@ -1,11 +1,14 @@
 use crate::actix::Actor as ActixActor;
 use crate::actix::ActorContext;
 use crate::actix::Addr;
+use crate::actix::Arbiter;
 use crate::actix::AsyncContext;
+use crate::actix::Context;
 use crate::actix::Handler;
 use crate::actix::Message;
 use crate::actix::Running;
 use crate::actix::StreamHandler;
+use crate::actix::System as ActixSystem;
 use crate::actix_web::middleware;
 use crate::actix_web::web;
 use crate::actix_web::web::Data as ActixData;
@@ -15,65 +18,143 @@ use crate::actix_web::Error as HttpError;
 use crate::actix_web::HttpRequest;
 use crate::actix_web::HttpResponse;
 use crate::actix_web::HttpServer as ActixHttpServer;
-use crate::actix_web_actors::ws::start as ws_start;
+use crate::actix_web_actors::ws::CloseCode;
+use crate::actix_web_actors::ws::CloseReason;
 use crate::actix_web_actors::ws::Message as WsMessage;
 use crate::actix_web_actors::ws::ProtocolError as WsProtocolError;
 use crate::actix_web_actors::ws::WebsocketContext;
 use crate::auth::AuthMode;
+use crate::bytes::BufMut;
+use crate::bytes::Bytes;
+use crate::bytes::BytesMut;
+use crate::client_outbox::ClientOutbox;
+use crate::client_outbox::PushOutcome;
+use crate::client_outbox::SlowConsumerPolicy;
+use crate::common_types::is_ping;
 use crate::common_types::CommonResponse;
-use crate::crossbeam_channel::unbounded as create_mpmc_channel;
-use crate::crossbeam_channel::SendError;
+use crate::common_types::JsonSerializable;
+use crate::common_types::MessagePayload;
 use crate::crossbeam_channel::Sender;
-use crate::crossbeam_utils::thread as scoped_thread;
 use crate::debug;
 use crate::error;
-use crate::futures::executor::spawn as spawn_future;
+use crate::frame_writer::start_with_frames;
+use crate::frame_writer::FrameWriter;
 use crate::futures::future::ok;
 use crate::futures::Future;
-use crate::futures_locks::RwLock as AsyncRwLock;
-use crate::futures_locks::RwLockWriteGuard;
 use crate::info;
+use crate::pubsub_filter::parse_document;
+use crate::pubsub_filter::SubscriptionFilter;
+use crate::rate_limiter::RateLimit;
+use crate::rate_limiter::RateLimiter;
+use crate::replay_buffer::ReplayBuffer;
+use crate::replay_buffer::ReplayResult;
+use crate::service_handle::notify_start_failed;
+use crate::service_handle::start_service;
+use crate::service_handle::ConnectedClients;
+use crate::service_handle::GoingAway;
+use crate::service_handle::ServiceHandle;
+use crate::service_handle::ServiceHandleSlot;
+use crate::service_handle::StartedNotifier;
+use crate::warn;
 use crate::ACTOR_MAILBOX_CAPACITY;
 use crate::NOTFOUND_MESSAGE;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
 use std::cell::Cell;
 use std::collections::HashMap;
-use std::sync::atomic::AtomicBool;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Result as IOResult;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::time::Duration;
-use std::time::Instant;
 
 pub type StaticStateArc = Arc<&'static PubsubWebsocketState>;
-pub type SendBroadcastFunction = Arc<dyn Fn(String) + Send + Sync>;
+pub type SendBroadcastFunction = Arc<dyn Fn(MessagePayload) + Send + Sync>;
+pub type SendTopicBroadcastFunction = Arc<dyn Fn(String, MessagePayload) + Send + Sync>;
+pub type SnapshotProvider = Arc<&'static (dyn Fn(Option<&str>) -> Option<MessagePayload> + Sync + Send)>;
+pub type ConflationKeyProvider = Arc<&'static (dyn Fn(Option<&str>, &MessagePayload) -> Option<String> + Sync + Send)>;
 type AsyncHttpResult = dyn Future<Item = HttpResponse, Error = HttpError>;
 type SyncHttpResult = Result<HttpResponse, HttpError>;
-type SubscribeResult = Result<(), SendError<BroadcastSubscribeSignal>>;
 type ClientAddress = Addr<PubsubBroadcastActor>;
-type ClientsDictionary = HashMap<ClientAddress, ()>;
+
+const MAX_TOPIC_LENGTH: usize = 128;
+const SLOW_CONSUMER_MESSAGE: &str = "Client is consuming messages too slowly";
+const INVALID_TOPIC_REQUEST_MESSAGE: &str = "Invalid request, expected \
+    {\"op\":\"subscribe\",\"topics\":[...],\"filter\":\"optional expression\",\"resume\":optional seq}";
+const REPLAY_GAP_MESSAGE: &str = "Gap too large, resnapshot";
 
 pub struct PubsubWebsocketConfig {
     pub binding_url: String,
     pub binding_path: String,
     pub max_clients: usize,
     pub client_timeout: Duration,
-    pub rapid_request_limit: Duration,
+    pub rate_limit: Option<RateLimit>,
+    pub client_buffer_size: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    /** Called with `None` when a client connects and with `Some(topic)` when it subscribes a topic.
+    The snapshot is queued before the client is registered, so it always precedes live messages.
+    It runs on the registry actor, keep it cheap */
+    pub snapshot_provider: Option<SnapshotProvider>,
+    /** Number of recent messages kept for resuming, 0 disables replay.
+    When enabled every text message is framed as `{"seq":N,"topic":...,"data":...}`
+    and every binary message is prefixed with its big-endian `u64` sequence.
+    A reconnecting client subscribes with `{"op":"subscribe","topics":[...],"resume":N}`: the messages after `N`
+    of the topics it was not yet subscribed to are queued before any live message of them, or a gap reply
+    followed by their snapshots. Untopiced messages are only replayed up to the connection of the client,
+    since it already received the later ones live */
+    pub replay_buffer_size: usize,
+    /** Called with the topic and payload of every published message. Pending messages of a lagging
+    client that share a conflation key are replaced by the newest one instead of being queued */
+    pub conflation_key_provider: Option<ConflationKeyProvider>,
     pub auth: AuthMode,
 }
 
 pub struct PubsubWebsocketState {
     pub active_clients: AtomicUsize,
     pub rejection_counter: AtomicUsize,
+    pub dropped_messages: AtomicUsize,
+    pub conflated_messages: AtomicUsize,
+    pub slow_consumer_disconnects: AtomicUsize,
     pub config: PubsubWebsocketConfig,
     subscribe_signaler: RwLock<Option<BroadcastSubscriber>>,
+    connected_clients: ConnectedClients,
+    service_handle: ServiceHandleSlot,
 }
 
 pub(crate) struct PubsubBroadcastActor {
-    last_request_stopwatch: Instant,
-    rapid_request_limit: Duration,
+    connected_clients: &'static ConnectedClients,
+    rate_limiter: RateLimiter,
     pubsub_signaler: Cell<BroadcastSubscriber>,
     client_closed_callback: Box<dyn Fn()>,
+    outbox: Arc<ClientOutbox>,
+    frames: FrameWriter,
+    client_handle: Option<ClientHandle>,
+}
+
+#[derive(Clone)]
+pub(crate) struct ClientHandle {
+    address: ClientAddress,
+    outbox: Arc<ClientOutbox>,
+}
+
+impl PartialEq for ClientHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
+
+impl Eq for ClientHandle {}
+
+impl Hash for ClientHandle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address.hash(state)
+    }
 }
 
 impl PubsubWebsocketState {
@@ -81,11 +162,21 @@ impl PubsubWebsocketState {
         Self {
             active_clients: AtomicUsize::new(0),
             rejection_counter: AtomicUsize::new(0),
+            dropped_messages: AtomicUsize::new(0),
+            conflated_messages: AtomicUsize::new(0),
+            slow_consumer_disconnects: AtomicUsize::new(0),
             config,
             subscribe_signaler: RwLock::new(None),
+            connected_clients: ConnectedClients::default(),
+            service_handle: ServiceHandleSlot::default(),
         }
     }
 
+    /// Available once the server is listening, `start_pubsub_websocket_service` also returns it
+    pub fn service_handle(&self) -> Option<ServiceHandle> {
+        self.service_handle.get()
+    }
+
     fn set_subscriber(&self, pubsub_signaler: BroadcastSubscriber) {
         let mut write_guard = self.subscribe_signaler.write().unwrap();
         *write_guard = Some(pubsub_signaler);
@@ -95,14 +186,22 @@ impl PubsubWebsocketState {
 impl PubsubBroadcastActor {
     fn new(
         config: &'static PubsubWebsocketConfig,
+        connected_clients: &'static ConnectedClients,
         pubsub_signaler: BroadcastSubscriber,
         client_closed_callback: Box<dyn Fn()>,
+        frames: FrameWriter,
     ) -> Self {
         Self {
-            last_request_stopwatch: Instant::now(),
-            rapid_request_limit: config.rapid_request_limit,
+            connected_clients,
+            rate_limiter: RateLimiter::new(config.rate_limit),
             pubsub_signaler: Cell::new(pubsub_signaler),
             client_closed_callback,
+            outbox: Arc::new(ClientOutbox::new(
+                config.client_buffer_size,
+                config.slow_consumer_policy,
+            )),
+            frames,
+            client_handle: None,
         }
     }
 }
@@ -112,93 +211,625 @@ impl ActixActor for PubsubBroadcastActor {
 
     fn started(&mut self, context: &mut Self::Context) {
         context.set_mailbox_capacity(ACTOR_MAILBOX_CAPACITY);
-        let _ = self.pubsub_signaler.get_mut().subscribe(context.address());
+        let client_handle = ClientHandle {
+            address: context.address(),
+            outbox: self.outbox.clone(),
+        };
+        self.pubsub_signaler.get_mut().subscribe(client_handle.clone());
+        self.client_handle = Some(client_handle);
+        self.connected_clients
+            .register(self.outbox.client_id(), context.address().recipient());
     }
 
-    fn stopping(&mut self, context: &mut Self::Context) -> Running {
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.frames.close();
+        self.connected_clients.unregister(&self.outbox.client_id());
         let subscriber = self.pubsub_signaler.take();
-        let _ = subscriber.unsubscribe(context.address());
+        if let Some(client_handle) = self.client_handle.take() {
+            subscriber.unsubscribe(client_handle);
+        }
+        let dropped_messages = self.outbox.dropped_messages();
+        if dropped_messages > 0 {
+            info!(
+                "Client {} dropped {} messages during its lifetime",
+                self.outbox.client_id(),
+                dropped_messages
+            );
+        }
         (*self.client_closed_callback)();
         Running::Stop
     }
 }
 
+impl PubsubBroadcastActor {
+    /// Replies go through the frame writer as well, so they keep their order with the published messages
+    fn reply(&self, text: String) {
+        self.frames.write(MessagePayload::Text(text).encode_frame());
+    }
+
+    fn handle_client_request(&mut self, text: &str) {
+        let mut response = CommonResponse::default();
+        let (op, topics, filter, resume, subscribe) = match PubsubRequest::from_json(text) {
+            Some(PubsubRequest::Subscribe { topics, filter, resume }) => ("subscribe", topics, filter, resume, true),
+            Some(PubsubRequest::Unsubscribe { topics }) => ("unsubscribe", topics, None, None, false),
+            None => {
+                response.error.push(INVALID_TOPIC_REQUEST_MESSAGE.to_owned());
+                self.reply(response.to_json());
+                return;
+            }
+        };
+        if let Some(invalid_topic) = topics.iter().find(|topic| !is_valid_topic(topic)) {
+            response.error.push(format!("Invalid topic '{}'", invalid_topic));
+            self.reply(response.to_json());
+            return;
+        }
+        let subscription_filter = match filter.as_ref().map(|filter| SubscriptionFilter::parse(filter)) {
+            Some(Ok(subscription_filter)) => Some(Arc::new(subscription_filter)),
+            Some(Err(filter_error)) => {
+                response.error.push(format!("Invalid filter: {}", filter_error));
+                self.reply(response.to_json());
+                return;
+            }
+            None => None,
+        };
+        let client_handle = match &self.client_handle {
+            Some(client_handle) => client_handle,
+            None => return,
+        };
+        let subscriber = self.pubsub_signaler.get_mut();
+        match resume {
+            Some(seq) => {
+                subscriber.resume_topics(client_handle.clone(), topics.clone(), subscription_filter.clone(), seq)
+            }
+            None => {
+                for topic in topics.iter() {
+                    if subscribe {
+                        subscriber.subscribe_topic(client_handle.clone(), topic.clone(), subscription_filter.clone())
+                    } else {
+                        subscriber.unsubscribe_topic(client_handle.clone(), topic.clone())
+                    }
+                }
+            }
+        }
+        response.result.insert("op".to_owned(), op.to_owned());
+        response.result.insert("topics".to_owned(), topics.join(","));
+        if let Some(subscription_filter) = subscription_filter {
+            response
+                .result
+                .insert("filter".to_owned(), subscription_filter.source().to_owned());
+        }
+        self.reply(response.to_json());
+    }
+}
+
+/** Encoded once at publish time, clones share the same payload and websocket frame across every recipient,
+so fanning out does not allocate per client */
 #[derive(Clone, Message)]
-pub struct BroadcastMessage(String);
+pub struct BroadcastMessage {
+    seq: u64,
+    payload: Arc<MessagePayload>,
+    frame: Bytes,
+    /// The published payload before sequence framing, subscription filters are evaluated against it
+    source: Arc<MessagePayload>,
+    conflation_key: Option<Arc<str>>,
+}
+
+impl BroadcastMessage {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn payload(&self) -> &MessagePayload {
+        &self.payload
+    }
+
+    pub fn conflation_key(&self) -> Option<&str> {
+        self.conflation_key.as_ref().map(Arc::as_ref)
+    }
+
+    /// Keys are assigned at publish time by `PubsubWebsocketConfig::conflation_key_provider`
+    pub(crate) fn with_conflation_key<K: Into<String>>(mut self, conflation_key: K) -> Self {
+        self.conflation_key = Some(Arc::from(conflation_key.into()));
+        self
+    }
+
+    pub(crate) fn frame(&self) -> &Bytes {
+        &self.frame
+    }
+
+    pub(crate) fn shared_conflation_key(&self) -> Option<&Arc<str>> {
+        self.conflation_key.as_ref()
+    }
+
+    fn sequenced(seq: u64, topic: Option<&str>, payload: MessagePayload, framed: bool) -> Self {
+        let source = Arc::new(payload);
+        let payload = match source.as_ref() {
+            MessagePayload::Text(text) if framed => {
+                Arc::new(MessagePayload::Text(frame_sequenced_text(seq, topic, text)))
+            }
+            MessagePayload::Binary(bytes) if framed => {
+                let mut framed_bytes = BytesMut::with_capacity(bytes.len() + 8);
+                framed_bytes.put_u64_be(seq);
+                framed_bytes.put_slice(bytes);
+                Arc::new(MessagePayload::Binary(framed_bytes.freeze()))
+            }
+            _ => source.clone(),
+        };
+        Self {
+            seq,
+            frame: payload.encode_frame(),
+            payload,
+            source,
+            conflation_key: None,
+        }
+    }
+}
+
+impl From<MessagePayload> for BroadcastMessage {
+    fn from(payload: MessagePayload) -> Self {
+        let payload = Arc::new(payload);
+        Self {
+            seq: 0,
+            frame: payload.encode_frame(),
+            payload: payload.clone(),
+            source: payload,
+            conflation_key: None,
+        }
+    }
+}
+
+/** Benchmark of the delivery path, pushes `message` through one outbox per subscriber and drains the frames
+written to their sockets. With `copy_payload` every frame is encoded from a copy of the payload, as each
+client did before frames were shared */
+#[cfg(feature = "bench")]
+pub fn fan_out_frames(subscribers: usize, message: &BroadcastMessage, copy_payload: bool) -> Vec<Bytes> {
+    let outboxes: Vec<ClientOutbox> = (0..subscribers)
+        .map(|_| ClientOutbox::new(1, SlowConsumerPolicy::default()))
+        .collect();
+    for outbox in outboxes.iter() {
+        outbox.push(message.clone());
+    }
+    outboxes
+        .iter()
+        .flat_map(|outbox| outbox.drain())
+        .map(|message| {
+            if copy_payload {
+                message.payload().clone().encode_frame()
+            } else {
+                message.frame().clone()
+            }
+        })
+        .collect()
+}
+
+fn frame_sequenced_text(seq: u64, topic: Option<&str>, payload: &str) -> String {
+    let topic = serde_json::to_string(&topic).unwrap();
+    if serde_json::from_str::<IgnoredAny>(payload).is_ok() {
+        format!("{{\"seq\":{},\"topic\":{},\"data\":{}}}", seq, topic, payload)
+    } else {
+        let payload = serde_json::to_string(payload).unwrap();
+        format!("{{\"seq\":{},\"topic\":{},\"data\":{}}}", seq, topic, payload)
+    }
+}
+
+#[derive(Message)]
+pub(crate) struct FlushOutbox;
+
+#[derive(Message)]
+pub(crate) struct SlowConsumerDisconnect(u16);
+
+impl Handler<FlushOutbox> for PubsubBroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, _: FlushOutbox, _: &mut Self::Context) {
+        for message in self.outbox.drain() {
+            self.frames.write(message.frame().clone());
+        }
+    }
+}
+
+impl Handler<GoingAway> for PubsubBroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, _: GoingAway, context: &mut Self::Context) {
+        context.close(Some(CloseCode::Away.into()));
+        context.stop();
+    }
+}
 
-impl Handler<BroadcastMessage> for PubsubBroadcastActor {
+impl Handler<SlowConsumerDisconnect> for PubsubBroadcastActor {
     type Result = ();
 
-    fn handle(&mut self, message: BroadcastMessage, context: &mut Self::Context) {
-        context.text(message.0);
+    fn handle(&mut self, signal: SlowConsumerDisconnect, context: &mut Self::Context) {
+        warn!(
+            "Disconnecting client {} after {} dropped messages",
+            self.outbox.client_id(),
+            self.outbox.dropped_messages()
+        );
+        context.close(Some(CloseReason {
+            code: CloseCode::from(signal.0),
+            description: Some(SLOW_CONSUMER_MESSAGE.to_owned()),
+        }));
+        context.stop();
     }
 }
 
+#[derive(Message)]
+pub(crate) struct PublishSignal {
+    topic: Option<String>,
+    payload: MessagePayload,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum PubsubRequest {
+    Subscribe {
+        topics: Vec<String>,
+        #[serde(default)]
+        filter: Option<String>,
+        #[serde(default)]
+        resume: Option<u64>,
+    },
+    Unsubscribe {
+        topics: Vec<String>,
+    },
+}
+
+impl JsonSerializable<'_> for PubsubRequest {}
+
+fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty() && topic.len() <= MAX_TOPIC_LENGTH && !topic.chars().any(char::is_whitespace)
+}
+
 impl StreamHandler<WsMessage, WsProtocolError> for PubsubBroadcastActor {
     fn handle(&mut self, payload: WsMessage, context: &mut Self::Context) {
-        if self.last_request_stopwatch.elapsed() < self.rapid_request_limit {
+        if let WsMessage::Close(_) = payload {
             context.stop();
             return;
         }
-        self.last_request_stopwatch = Instant::now();
+        if !self.rate_limiter.admit(context) {
+            return;
+        }
         match payload {
-            WsMessage::Close(_) => context.stop(),
             WsMessage::Ping(ping_payload) => context.pong(&ping_payload),
             WsMessage::Text(text) => {
-                if text.len() < 4 {
+                if is_ping(&text) {
+                    self.reply("pong".to_owned());
                     return;
                 }
-                if let "ping" = &text.to_lowercase()[0..4] {
-                    context.text("pong")
-                }
+                self.handle_client_request(&text);
             }
             _ => (),
         }
     }
 }
 
+#[derive(Message)]
 pub(crate) enum BroadcastSubscribeSignal {
-    Subscribe(ClientAddress),
-    Unsubcribe(ClientAddress),
+    Subscribe(ClientHandle),
+    Unsubcribe(ClientHandle),
+    SubscribeTopic(ClientHandle, String, Option<Arc<SubscriptionFilter>>),
+    UnsubscribeTopic(ClientHandle, String),
+    ResumeTopics(ClientHandle, Vec<String>, Option<Arc<SubscriptionFilter>>, u64),
+}
+
+pub(crate) struct BroadcastRegistry {
+    subscriptions: SubscriptionRegistry<ClientHandle>,
+    history: ReplayBuffer<BroadcastMessage>,
+    /// Last sequence published before each client connected
+    connected_seqs: HashMap<ClientHandle, u64>,
+    conflation_key_provider: Option<ConflationKeyProvider>,
+}
+
+impl BroadcastRegistry {
+    fn new(
+        max_clients: usize,
+        replay_buffer_size: usize,
+        conflation_key_provider: Option<ConflationKeyProvider>,
+    ) -> Self {
+        Self {
+            subscriptions: SubscriptionRegistry::with_capacity(max_clients),
+            history: ReplayBuffer::new(replay_buffer_size),
+            connected_seqs: HashMap::with_capacity(max_clients),
+            conflation_key_provider,
+        }
+    }
+
+    fn publish(&mut self, topic: Option<String>, payload: MessagePayload) -> BroadcastMessage {
+        let seq = self.history.next_seq();
+        let framed = self.history.is_enabled();
+        let topic_name = topic.as_ref().map(String::as_str);
+        let conflation_key = self
+            .conflation_key_provider
+            .as_ref()
+            .and_then(|conflation_key_provider| conflation_key_provider(topic_name, &payload));
+        let mut message = BroadcastMessage::sequenced(seq, topic_name, payload, framed);
+        if let Some(conflation_key) = conflation_key {
+            message = message.with_conflation_key(conflation_key);
+        }
+        self.history.record(seq, topic, message.clone());
+        message
+    }
+
+    fn replay(&self, client: &ClientHandle, seq: u64, topics: &HashSet<String>) -> ReplayResult<BroadcastMessage> {
+        let connected_seq = self.connected_seqs.get(client).cloned().unwrap_or(0);
+        self.history.replay_since(seq, |topic, message| match topic {
+            Some(topic) => topics.contains(topic) && self.subscriptions.accepts(client, topic, &message.source),
+            None => message.seq <= connected_seq,
+        })
+    }
+}
+
+type TopicFilter = Option<Arc<SubscriptionFilter>>;
+
+pub(crate) struct SubscriptionRegistry<C: Hash + Eq + Clone> {
+    clients: HashMap<C, HashSet<String>>,
+    topics: HashMap<String, HashMap<C, TopicFilter>>,
+}
+
+impl<C: Hash + Eq + Clone> SubscriptionRegistry<C> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            clients: HashMap::with_capacity(capacity),
+            topics: HashMap::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    fn insert_client(&mut self, client: C) {
+        self.clients.entry(client).or_insert_with(HashSet::new);
+    }
+
+    fn remove_client(&mut self, client: &C) -> bool {
+        let client_topics = match self.clients.remove(client) {
+            Some(client_topics) => client_topics,
+            None => return false,
+        };
+        for topic in client_topics.iter() {
+            self.remove_from_topic(client, topic);
+        }
+        true
+    }
+
+    /// Subscribing an already subscribed topic replaces its filter
+    fn subscribe_topic(&mut self, client: &C, topic: String, filter: TopicFilter) -> bool {
+        match self.clients.get_mut(client) {
+            Some(client_topics) => {
+                client_topics.insert(topic.clone());
+                self.topics
+                    .entry(topic)
+                    .or_insert_with(HashMap::new)
+                    .insert(client.clone(), filter);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn is_subscribed(&self, client: &C, topic: &str) -> bool {
+        self.clients
+            .get(client)
+            .map(|client_topics| client_topics.contains(topic))
+            .unwrap_or(false)
+    }
+
+    fn accepts(&self, client: &C, topic: &str, payload: &MessagePayload) -> bool {
+        match self
+            .topics
+            .get(topic)
+            .and_then(|topic_clients| topic_clients.get(client))
+        {
+            Some(Some(filter)) => filter.matches_payload(payload),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    fn unsubscribe_topic(&mut self, client: &C, topic: &str) -> bool {
+        let unsubscribed = self
+            .clients
+            .get_mut(client)
+            .map_or(false, |client_topics| client_topics.remove(topic));
+        if unsubscribed {
+            self.remove_from_topic(client, topic);
+        }
+        unsubscribed
+    }
+
+    fn remove_from_topic(&mut self, client: &C, topic: &str) {
+        if let Some(topic_clients) = self.topics.get_mut(topic) {
+            topic_clients.remove(client);
+            if topic_clients.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+
+    /// Untopiced broadcasts are never filtered
+    fn for_each_recipient<F: FnMut(&C, Option<&SubscriptionFilter>)>(&self, topic: Option<&str>, mut send: F) {
+        match topic {
+            Some(topic) => {
+                if let Some(topic_clients) = self.topics.get(topic) {
+                    for (client, filter) in topic_clients.iter() {
+                        send(client, filter.as_ref().map(Arc::as_ref));
+                    }
+                }
+            }
+            None => self.clients.keys().for_each(|client| send(client, None)),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub(crate) struct BroadcastSubscriber {
-    subscribe_signaler: Option<Sender<BroadcastSubscribeSignal>>,
+    registry: Option<Addr<BroadcastRegistryActor>>,
 }
 
 impl Default for BroadcastSubscriber {
     fn default() -> Self {
-        Self {
-            subscribe_signaler: None,
-        }
+        Self { registry: None }
     }
 }
 
 impl BroadcastSubscriber {
-    fn new(subscribe_signaler: Sender<BroadcastSubscribeSignal>) -> Self {
+    fn new(registry: Addr<BroadcastRegistryActor>) -> Self {
         Self {
-            subscribe_signaler: Some(subscribe_signaler),
+            registry: Some(registry),
         }
     }
 
-    fn subscribe(&self, client_identity: ClientAddress) -> SubscribeResult {
-        if self.subscribe_signaler.is_none() {
-            panic!("The websocket client is trying to register itself without a subscriber!")
+    fn signal(&self, subscribe_signal: BroadcastSubscribeSignal) {
+        match &self.registry {
+            Some(registry) => registry.do_send(subscribe_signal),
+            None => panic!("The websocket client is trying to register itself without a subscriber!"),
+        }
+    }
+
+    fn subscribe(&self, client_identity: ClientHandle) {
+        self.signal(BroadcastSubscribeSignal::Subscribe(client_identity))
+    }
+
+    fn unsubscribe(self, client_identity: ClientHandle) {
+        self.signal(BroadcastSubscribeSignal::Unsubcribe(client_identity))
+    }
+
+    fn subscribe_topic(&self, client_identity: ClientHandle, topic: String, filter: Option<Arc<SubscriptionFilter>>) {
+        self.signal(BroadcastSubscribeSignal::SubscribeTopic(client_identity, topic, filter))
+    }
+
+    fn resume_topics(
+        &self,
+        client_identity: ClientHandle,
+        topics: Vec<String>,
+        filter: Option<Arc<SubscriptionFilter>>,
+        seq: u64,
+    ) {
+        self.signal(BroadcastSubscribeSignal::ResumeTopics(
+            client_identity,
+            topics,
+            filter,
+            seq,
+        ))
+    }
+
+    fn unsubscribe_topic(&self, client_identity: ClientHandle, topic: String) {
+        self.signal(BroadcastSubscribeSignal::UnsubscribeTopic(client_identity, topic))
+    }
+}
+
+/** Owns the subscriptions and the replay history, subscribe/unsubscribe and publish are
+serialized through its mailbox so a snapshot or a replay can never interleave with a live message */
+pub(crate) struct BroadcastRegistryActor {
+    state: &'static PubsubWebsocketState,
+    registry: BroadcastRegistry,
+}
+
+impl BroadcastRegistryActor {
+    fn new(state: &'static PubsubWebsocketState) -> Self {
+        let PubsubWebsocketConfig {
+            max_clients,
+            replay_buffer_size,
+            conflation_key_provider,
+            ..
+        } = &state.config;
+        Self {
+            state,
+            registry: BroadcastRegistry::new(*max_clients, *replay_buffer_size, conflation_key_provider.clone()),
         }
-        self.subscribe_signaler
-            .as_ref()
-            .unwrap()
-            .send(BroadcastSubscribeSignal::Subscribe(client_identity))
     }
+}
+
+impl ActixActor for BroadcastRegistryActor {
+    type Context = Context<Self>;
+}
 
-    fn unsubscribe(self, client_identity: ClientAddress) -> SubscribeResult {
-        if self.subscribe_signaler.is_none() {
-            panic!("The websocket client is trying to register itself without a subscriber!")
+impl Handler<BroadcastSubscribeSignal> for BroadcastRegistryActor {
+    type Result = ();
+
+    fn handle(&mut self, subscribe_signal: BroadcastSubscribeSignal, _: &mut Self::Context) {
+        let subscriptions = &mut self.registry.subscriptions;
+        match subscribe_signal {
+            BroadcastSubscribeSignal::Subscribe(client) => {
+                deliver_snapshot(self.state, &client, None);
+                let connected_seq = self.registry.history.last_seq();
+                self.registry.connected_seqs.insert(client.clone(), connected_seq);
+                subscriptions.insert_client(client);
+                info!(
+                    "A Client just subscribed, current client count is {}",
+                    subscriptions.len()
+                );
+            }
+            BroadcastSubscribeSignal::Unsubcribe(client) => {
+                self.registry.connected_seqs.remove(&client);
+                subscriptions.remove_client(&client);
+                info!(
+                    "A Client just unsubscribed, current client count is {}",
+                    subscriptions.len()
+                );
+            }
+            BroadcastSubscribeSignal::SubscribeTopic(client, topic, filter) => {
+                if !subscriptions.is_subscribed(&client, &topic) {
+                    deliver_snapshot(self.state, &client, Some(&topic));
+                }
+                if subscriptions.subscribe_topic(&client, topic.clone(), filter) {
+                    debug!("A Client just subscribed to topic {}", topic);
+                }
+            }
+            BroadcastSubscribeSignal::UnsubscribeTopic(client, topic) => {
+                if subscriptions.unsubscribe_topic(&client, &topic) {
+                    debug!("A Client just unsubscribed from topic {}", topic);
+                }
+            }
+            BroadcastSubscribeSignal::ResumeTopics(client, topics, filter, seq) => {
+                // Topics the client already had keep streaming live, replaying them would duplicate messages
+                let resumed_topics: HashSet<String> = topics
+                    .into_iter()
+                    .filter(|topic| !subscriptions.is_subscribed(&client, topic))
+                    .collect();
+                for topic in resumed_topics.iter() {
+                    subscriptions.subscribe_topic(&client, topic.clone(), filter.clone());
+                }
+                let replay = self.registry.replay(&client, seq, &resumed_topics);
+                let gap = match replay {
+                    ReplayResult::Gap { .. } => true,
+                    ReplayResult::Replay(_) => false,
+                };
+                deliver_replay(&client, seq, replay);
+                if gap {
+                    for topic in resumed_topics.iter() {
+                        deliver_snapshot(self.state, &client, Some(topic));
+                    }
+                }
+            }
         }
-        self.subscribe_signaler
-            .unwrap()
-            .send(BroadcastSubscribeSignal::Unsubcribe(client_identity))
+    }
+}
+
+impl Handler<PublishSignal> for BroadcastRegistryActor {
+    type Result = ();
+
+    fn handle(&mut self, PublishSignal { topic, payload }: PublishSignal, _: &mut Self::Context) {
+        let message = self.registry.publish(topic.clone(), payload);
+        let state = self.state;
+        let topic = topic.as_ref().map(String::as_str);
+        // Parsed at most once per publish, and only when a recipient has a filter
+        let mut document: Option<Option<Value>> = None;
+        self.registry.subscriptions.for_each_recipient(topic, |client, filter| {
+            if let Some(filter) = filter {
+                let document = document.get_or_insert_with(|| parse_document(&message.source));
+                if !document
+                    .as_ref()
+                    .map(|document| filter.matches(document))
+                    .unwrap_or(false)
+                {
+                    return;
+                }
+            }
+            deliver_to_client(state, client, message.clone());
+        });
     }
 }
 
@@ -221,7 +852,10 @@ fn reject_unmapped_handler(shared_state: ActixData<StaticStateArc>) -> Box<Async
 
 fn ws_upgrader(shared_state: ActixData<StaticStateArc>, request: HttpRequest, stream: Payload) -> SyncHttpResult {
     let PubsubWebsocketState {
-        active_clients, config, ..
+        active_clients,
+        config,
+        connected_clients,
+        ..
     } = shared_state.get_ref().as_ref();
     config.auth.validate(&request)?;
     let onclose_callback = Box::new(move || {
@@ -233,8 +867,15 @@ fn ws_upgrader(shared_state: ActixData<StaticStateArc>, request: HttpRequest, st
     });
     let subscribe_signaler_guard = shared_state.subscribe_signaler.read().unwrap();
     let cloned_subscribe_signaler = subscribe_signaler_guard.as_ref().unwrap().clone();
-    let pubsub_broadcast_actor = PubsubBroadcastActor::new(&config, cloned_subscribe_signaler, onclose_callback);
-    let upgrade_result = ws_start(pubsub_broadcast_actor, &request, stream);
+    let frames = FrameWriter::default();
+    let pubsub_broadcast_actor = PubsubBroadcastActor::new(
+        &config,
+        &connected_clients,
+        cloned_subscribe_signaler,
+        onclose_callback,
+        frames.clone(),
+    );
+    let upgrade_result = start_with_frames(pubsub_broadcast_actor, frames, &request, stream);
     match upgrade_result {
         Ok(ok_result) => {
             let active_clients = shared_state.active_clients.fetch_add(1, Ordering::Relaxed);
@@ -251,102 +892,284 @@ fn ws_upgrader(shared_state: ActixData<StaticStateArc>, request: HttpRequest, st
     }
 }
 
-pub fn run_pubsub_websocket_service(state: StaticStateArc, send_broadcast_fn: Sender<SendBroadcastFunction>) {
-    // Section broadcast pubsub threads
-    let shutdown_signal = AtomicBool::new(false);
-    let (subscribe_signaler, subscribe_listener) = create_mpmc_channel::<BroadcastSubscribeSignal>();
-    state.set_subscriber(BroadcastSubscriber::new(subscribe_signaler));
-    let (publisher_sender, publisher_receiver) = create_mpmc_channel::<BroadcastMessage>();
-    let broadcaster = move |message: String| {
-        let _ = publisher_sender.send(BroadcastMessage(message));
+fn deliver_snapshot(state: &PubsubWebsocketState, client: &ClientHandle, topic: Option<&str>) {
+    let snapshot_provider = match &state.config.snapshot_provider {
+        Some(snapshot_provider) => snapshot_provider,
+        None => return,
     };
-    let _ = send_broadcast_fn.send(Arc::new(broadcaster));
-    info!("Broadcaster callback sent, running Pubsub Broadcast thread...");
-    let max_clients = state.config.max_clients;
-    let subscribers: ClientsDictionary = HashMap::with_capacity(max_clients);
-    let rw_lock_registrar = AsyncRwLock::new(subscribers);
-    let rw_lock_publisher = rw_lock_registrar.clone();
-    let no_message_timeout = Duration::from_secs(1);
-    let client_timeout = state.config.client_timeout.as_millis() as u64;
-    scoped_thread::scope(|s| {
-        // Subscribe/Unsubscribe registration thread
-        s.spawn(|_| {
-            let rw_lock = rw_lock_registrar;
-            let no_message_timeout = Duration::from_secs(1);
-            let insert_client_func = |mut clients: RwLockWriteGuard<ClientsDictionary>,
-                                      client_address: ClientAddress| {
-                clients.entry(client_address).or_insert(());
-                info!("A Client just subscribed, current client count is {}", clients.len());
-            };
-            let remove_client_func = |mut clients: RwLockWriteGuard<ClientsDictionary>,
-                                      client_address: ClientAddress| {
-                clients.remove(&client_address).unwrap();
-                info!("A Client just unsubscribed, current client count is {}", clients.len());
-            };
-            loop {
-                if shutdown_signal.load(Ordering::Relaxed) {
-                    break;
-                }
-                if let Ok(subscribe_signal) = subscribe_listener.recv_timeout(no_message_timeout) {
-                    match subscribe_signal {
-                        BroadcastSubscribeSignal::Subscribe(client_addr) => {
-                            let _ = rw_lock
-                                .write()
-                                .map(|clients| insert_client_func(clients, client_addr))
-                                .wait();
-                        }
-                        BroadcastSubscribeSignal::Unsubcribe(client_addr) => {
-                            let _ = rw_lock
-                                .write()
-                                .map(|clients| remove_client_func(clients, client_addr))
-                                .wait();
-                        }
-                    }
-                }
-            }
+    if let Some(snapshot) = snapshot_provider(topic) {
+        if client.outbox.push_snapshot(BroadcastMessage::from(snapshot)) {
+            client.address.do_send(FlushOutbox);
+        }
+    }
+}
+
+fn deliver_replay(client: &ClientHandle, seq: u64, replay: ReplayResult<BroadcastMessage>) {
+    let mut response = CommonResponse::default();
+    response.result.insert("op".to_owned(), "resume".to_owned());
+    response.result.insert("seq".to_owned(), seq.to_string());
+    let messages = match replay {
+        ReplayResult::Replay(messages) => {
+            response
+                .result
+                .insert("replayed".to_owned(), messages.len().to_string());
+            messages
+        }
+        ReplayResult::Gap { oldest_seq, last_seq } => {
+            response.error.push(REPLAY_GAP_MESSAGE.to_owned());
+            response.result.insert("oldest_seq".to_owned(), oldest_seq.to_string());
+            response.result.insert("last_seq".to_owned(), last_seq.to_string());
+            Vec::new()
+        }
+    };
+    let mut wake = client
+        .outbox
+        .push_snapshot(BroadcastMessage::from(MessagePayload::Text(response.to_json())));
+    for message in messages {
+        wake |= client.outbox.push_snapshot(message);
+    }
+    if wake {
+        client.address.do_send(FlushOutbox);
+    }
+}
+
+fn deliver_to_client(state: &PubsubWebsocketState, client: &ClientHandle, message: BroadcastMessage) {
+    match client.outbox.push(message) {
+        PushOutcome::Wake => client.address.do_send(FlushOutbox),
+        PushOutcome::Queued => (),
+        PushOutcome::Conflated => {
+            state.conflated_messages.fetch_add(1, Ordering::Relaxed);
+        }
+        PushOutcome::Dropped => {
+            state.dropped_messages.fetch_add(1, Ordering::Relaxed);
+        }
+        PushOutcome::Disconnect(close_code) => {
+            state.dropped_messages.fetch_add(1, Ordering::Relaxed);
+            state.slow_consumer_disconnects.fetch_add(1, Ordering::Relaxed);
+            client.address.do_send(SlowConsumerDisconnect(close_code));
+        }
+    }
+}
+
+/// Panics when the server cannot bind `binding_url`
+pub fn run_pubsub_websocket_service(
+    state: StaticStateArc,
+    send_broadcast_fn: Sender<SendBroadcastFunction>,
+    send_topic_broadcast_fn: Sender<SendTopicBroadcastFunction>,
+) {
+    if let Err(run_error) = serve_pubsub_websocket(state, send_broadcast_fn, send_topic_broadcast_fn, None) {
+        panic!("Pubsub websocket service failed to start: {}", run_error);
+    }
+}
+
+/// Runs the service on a thread of its own, returns once the server is listening
+pub fn start_pubsub_websocket_service(
+    state: StaticStateArc,
+    send_broadcast_fn: Sender<SendBroadcastFunction>,
+    send_topic_broadcast_fn: Sender<SendTopicBroadcastFunction>,
+) -> IOResult<ServiceHandle> {
+    start_service("pubsub-websocket", move |started| {
+        if let Err(run_error) = serve_pubsub_websocket(state, send_broadcast_fn, send_topic_broadcast_fn, Some(started))
+        {
+            error!("Pubsub websocket service failed to start: {}", run_error);
+        }
+    })
+}
+
+fn serve_pubsub_websocket(
+    state: StaticStateArc,
+    send_broadcast_fn: Sender<SendBroadcastFunction>,
+    send_topic_broadcast_fn: Sender<SendTopicBroadcastFunction>,
+    started: Option<StartedNotifier>,
+) -> IOResult<()> {
+    let PubsubWebsocketConfig {
+        binding_url,
+        binding_path,
+        max_clients,
+        client_timeout,
+        ..
+    } = &state.config;
+    let registry_state: &'static PubsubWebsocketState = *state;
+    let system = ActixSystem::new("pubsub-websocket");
+    let mut registry_arbiter = Arbiter::new();
+    let registry = BroadcastRegistryActor::start_in_arbiter(&registry_arbiter, move |_| {
+        BroadcastRegistryActor::new(registry_state)
+    });
+    state.set_subscriber(BroadcastSubscriber::new(registry.clone()));
+    let topic_registry = registry.clone();
+    let broadcaster = move |payload: MessagePayload| {
+        registry.do_send(PublishSignal { topic: None, payload });
+    };
+    let topic_broadcaster = move |topic: String, payload: MessagePayload| {
+        topic_registry.do_send(PublishSignal {
+            topic: Some(topic),
+            payload,
         });
-        // Message broadcast thread
-        s.spawn(|_| {
-            let rw_lock = rw_lock_publisher;
-            loop {
-                if shutdown_signal.load(Ordering::Relaxed) {
-                    break;
-                }
-                if let Ok(message) = publisher_receiver.recv_timeout(no_message_timeout) {
-                    let async_read = rw_lock.read().map(|clients| {
-                        for (client, _) in clients.iter() {
-                            client.do_send(message.clone());
-                        }
-                    });
-                    let _ = spawn_future(async_read).wait_future();
-                }
+    };
+    let _ = send_broadcast_fn.send(Arc::new(broadcaster));
+    let _ = send_topic_broadcast_fn.send(Arc::new(topic_broadcaster));
+    info!("Broadcaster callbacks sent, running Actix Websocket server...");
+    let connected_clients = &state.connected_clients;
+    let service_handle = &state.service_handle;
+    let client_timeout = client_timeout.as_millis() as u64;
+    let shared_data = ActixData::new(state);
+    let bound = ActixHttpServer::new(move || {
+        ActixApp::new()
+            .register_data(shared_data.clone())
+            .wrap(middleware::Logger::default())
+            .service(web::resource(&binding_path).route(web::get().to(ws_upgrader)))
+            .default_service(web::route().to_async(reject_unmapped_handler))
+    })
+    .maxconn(*max_clients)
+    .client_timeout(client_timeout)
+    .client_shutdown(client_timeout)
+    .shutdown_timeout(1)
+    .system_exit()
+    .bind(binding_url);
+    let server = match bound {
+        Ok(server) => server.start(),
+        Err(bind_error) => {
+            registry_arbiter.stop();
+            let _ = registry_arbiter.join();
+            return Err(notify_start_failed(started, bind_error));
+        }
+    };
+    let (stop_notifier, handle) = ServiceHandle::new(server, connected_clients);
+    service_handle.set(handle, started);
+    let _ = system.run();
+    registry_arbiter.stop();
+    let _ = registry_arbiter.join();
+    let _ = stop_notifier.send(());
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_client_request_parsing() {
+        let request = PubsubRequest::from_json(r#"{"op":"subscribe","topics":["trades.BTC_USDT"]}"#);
+        match request {
+            Some(PubsubRequest::Subscribe { topics, filter, resume }) => {
+                assert_eq!(topics, vec!["trades.BTC_USDT".to_owned()]);
+                assert_eq!(filter, None);
+                assert_eq!(resume, None);
             }
+            _ => panic!("subscribe request should be parsed"),
+        }
+        match PubsubRequest::from_json(r#"{"op":"subscribe","topics":["trades"],"filter":"qty > 1"}"#) {
+            Some(PubsubRequest::Subscribe { filter, .. }) => assert_eq!(filter, Some("qty > 1".to_owned())),
+            _ => panic!("filtered subscribe request should be parsed"),
+        }
+        match PubsubRequest::from_json(r#"{"op":"subscribe","topics":["trades"],"resume":42}"#) {
+            Some(PubsubRequest::Subscribe { resume, .. }) => assert_eq!(resume, Some(42)),
+            _ => panic!("resuming subscribe request should be parsed"),
+        }
+        assert!(PubsubRequest::from_json(r#"{"op":"unsubscribe","topics":[]}"#).is_some());
+        assert!(PubsubRequest::from_json(r#"{"op":"publish","topics":["trades"]}"#).is_none());
+        assert!(PubsubRequest::from_json("subscribe trades").is_none());
+    }
+
+    #[test]
+    fn test_sequenced_payload_framing() {
+        assert_eq!(
+            frame_sequenced_text(7, Some("trades"), r#"{"price":1}"#),
+            r#"{"seq":7,"topic":"trades","data":{"price":1}}"#
+        );
+        assert_eq!(
+            frame_sequenced_text(8, None, "plain text"),
+            r#"{"seq":8,"topic":null,"data":"plain text"}"#
+        );
+        let binary_message = BroadcastMessage::sequenced(9, None, MessagePayload::from(vec![0xff]), true);
+        assert_eq!(binary_message.payload().as_bytes(), &[0, 0, 0, 0, 0, 0, 0, 9, 0xff]);
+    }
+
+    #[test]
+    fn test_topic_validation() {
+        assert!(is_valid_topic("trades.BTC_USDT"));
+        assert!(!is_valid_topic(""));
+        assert!(!is_valid_topic("trades BTC_USDT"));
+        assert!(!is_valid_topic(&"a".repeat(MAX_TOPIC_LENGTH + 1)));
+    }
+
+    fn recipients(registry: &SubscriptionRegistry<i32>, topic: Option<&str>) -> Vec<i32> {
+        let mut recipients = Vec::new();
+        registry.for_each_recipient(topic, |client, _| recipients.push(*client));
+        recipients.sort();
+        recipients
+    }
+
+    #[test]
+    fn test_subscription_registry_routes_by_topic() {
+        let mut registry = SubscriptionRegistry::with_capacity(4);
+        registry.insert_client(1);
+        registry.insert_client(2);
+        assert!(registry.subscribe_topic(&1, "trades.BTC_USDT".to_owned(), None));
+        assert!(!registry.subscribe_topic(&3, "trades.BTC_USDT".to_owned(), None));
+
+        assert_eq!(recipients(&registry, Some("trades.BTC_USDT")), vec![1]);
+        assert_eq!(recipients(&registry, Some("trades.ETH_USDT")), Vec::<i32>::new());
+        assert_eq!(recipients(&registry, None), vec![1, 2]);
+
+        assert!(registry.is_subscribed(&1, "trades.BTC_USDT"));
+        assert!(!registry.is_subscribed(&2, "trades.BTC_USDT"));
+        assert!(registry.unsubscribe_topic(&1, "trades.BTC_USDT"));
+        assert!(!registry.unsubscribe_topic(&1, "trades.BTC_USDT"));
+        assert!(registry.topics.is_empty());
+
+        registry.subscribe_topic(&2, "trades.BTC_USDT".to_owned(), None);
+        assert!(registry.remove_client(&2));
+        assert!(registry.topics.is_empty());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_subscription_filters_are_kept_per_subscription() {
+        let large_trades = Arc::new(SubscriptionFilter::parse("qty >= 10").unwrap());
+        let mut registry = SubscriptionRegistry::with_capacity(4);
+        registry.insert_client(1);
+        registry.insert_client(2);
+        registry.subscribe_topic(&1, "trades".to_owned(), Some(large_trades.clone()));
+        registry.subscribe_topic(&2, "trades".to_owned(), None);
+        registry.subscribe_topic(&2, "orders".to_owned(), Some(large_trades));
+
+        let small_trade = MessagePayload::from(r#"{"qty":1}"#);
+        let large_trade = MessagePayload::from(r#"{"qty":12}"#);
+        assert!(!registry.accepts(&1, "trades", &small_trade));
+        assert!(registry.accepts(&1, "trades", &large_trade));
+        assert!(registry.accepts(&2, "trades", &small_trade));
+        assert!(!registry.accepts(&2, "orders", &small_trade));
+        assert!(!registry.accepts(&1, "orders", &large_trade));
+
+        registry.subscribe_topic(&1, "trades".to_owned(), None);
+        assert!(registry.accepts(&1, "trades", &small_trade));
+        let mut filtered = Vec::new();
+        registry.for_each_recipient(Some("orders"), |client, filter| {
+            filtered.push((*client, filter.is_some()))
         });
-        // Websocket server thread
-        s.spawn(|_| {
-            let PubsubWebsocketConfig {
-                binding_url,
-                binding_path,
-                ..
-            } = &state.config;
-            let shared_data = ActixData::new(state);
-            info!("Running Actix Websocket server...");
-            let _ = ActixHttpServer::new(move || {
-                ActixApp::new()
-                    .register_data(shared_data.clone())
-                    .wrap(middleware::Logger::default())
-                    .service(web::resource(&binding_path).route(web::get().to(ws_upgrader)))
-                    .default_service(web::route().to_async(reject_unmapped_handler))
-            })
-            .maxconn(max_clients)
-            .client_timeout(client_timeout)
-            .client_shutdown(client_timeout)
-            .shutdown_timeout(1)
-            .bind(binding_url)
-            .unwrap()
-            .run();
-            shutdown_signal.store(true, Ordering::Relaxed);
-        });
-    })
-    .unwrap();
+        assert_eq!(filtered, vec![(2, true)]);
+    }
+
+    #[test]
+    fn test_sequenced_message_keeps_unframed_source() {
+        let message = BroadcastMessage::sequenced(3, Some("trades"), MessagePayload::from(r#"{"qty":2}"#), true);
+        assert_eq!(
+            message.payload().as_text(),
+            Some(r#"{"seq":3,"topic":"trades","data":{"qty":2}}"#)
+        );
+        assert_eq!(message.source.as_text(), Some(r#"{"qty":2}"#));
+        let unframed = BroadcastMessage::sequenced(4, None, MessagePayload::from("plain"), false);
+        assert!(Arc::ptr_eq(&unframed.payload, &unframed.source));
+    }
+
+    #[test]
+    fn test_publish_attaches_conflation_key() {
+        let ticker_key: ConflationKeyProvider =
+            Arc::new(&|topic: Option<&str>, _: &MessagePayload| topic.map(|topic| format!("ticker:{}", topic)));
+        let mut registry = BroadcastRegistry::new(1, 0, Some(ticker_key));
+        let message = registry.publish(Some("BTC_USDT".to_owned()), MessagePayload::from("1"));
+        assert_eq!(message.conflation_key(), Some("ticker:BTC_USDT"));
+        assert_eq!(registry.publish(None, MessagePayload::from("2")).conflation_key(), None);
+    }
 }
@@ -0,0 +1,330 @@
+use crate::broadcast_pubsub::BroadcastMessage;
+use crate::uuid::Uuid;
+use crate::warn;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlowConsumerPolicy {
+    /// Evict the oldest pending message to make room for the new one
+    DropOldest,
+    /// Discard the new message, keep what is already pending
+    DropNewest,
+    /// Discard the new message and close with `close_code` after `max_overflows` overflows
+    Disconnect { close_code: u16, max_overflows: usize },
+}
+
+impl Default for SlowConsumerPolicy {
+    fn default() -> Self {
+        Self::DropNewest
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum PushOutcome {
+    /// The outbox was empty, the client must be woken up to flush it
+    Wake,
+    Queued,
+    /// A pending message with the same conflation key was replaced
+    Conflated,
+    Dropped,
+    Disconnect(u16),
+}
+
+pub(crate) struct ClientOutbox {
+    client_id: Uuid,
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    pending: Mutex<PendingQueue>,
+    dropped_messages: AtomicUsize,
+    overflows: AtomicUsize,
+    congested: AtomicBool,
+}
+
+impl ClientOutbox {
+    pub(crate) fn new(capacity: usize, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            client_id: Uuid::new_v4(),
+            capacity: capacity.max(1),
+            policy,
+            pending: Mutex::new(PendingQueue::default()),
+            dropped_messages: AtomicUsize::new(0),
+            overflows: AtomicUsize::new(0),
+            congested: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn client_id(&self) -> Uuid {
+        self.client_id
+    }
+
+    pub(crate) fn dropped_messages(&self) -> usize {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn push(&self, message: BroadcastMessage) -> PushOutcome {
+        let mut pending = self.pending.lock().unwrap();
+        let conflated = message
+            .shared_conflation_key()
+            .map(|key| pending.has_key(key))
+            .unwrap_or(false);
+        if conflated {
+            pending.push_back(message, false);
+            return PushOutcome::Conflated;
+        }
+        if pending.len() < self.capacity {
+            let idle = pending.is_empty();
+            pending.push_back(message, false);
+            return if idle { PushOutcome::Wake } else { PushOutcome::Queued };
+        }
+        self.record_drop();
+        match self.policy {
+            SlowConsumerPolicy::DropOldest => {
+                // Snapshots are never evicted, when only snapshots are pending the new message is dropped
+                if pending.evict_oldest() {
+                    pending.push_back(message, false);
+                }
+                PushOutcome::Dropped
+            }
+            SlowConsumerPolicy::DropNewest => PushOutcome::Dropped,
+            SlowConsumerPolicy::Disconnect {
+                close_code,
+                max_overflows,
+            } => {
+                let overflows = self.overflows.fetch_add(1, Ordering::Relaxed) + 1;
+                if overflows >= max_overflows {
+                    PushOutcome::Disconnect(close_code)
+                } else {
+                    PushOutcome::Dropped
+                }
+            }
+        }
+    }
+
+    /** Snapshots, resume replies and replayed messages bypass the slow-consumer policy and are never evicted,
+    returns whether the client must be woken up */
+    pub(crate) fn push_snapshot(&self, message: BroadcastMessage) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let idle = pending.is_empty();
+        pending.push_back(message, true);
+        idle
+    }
+
+    pub(crate) fn drain(&self) -> Vec<BroadcastMessage> {
+        let mut pending = self.pending.lock().unwrap();
+        self.congested.store(false, Ordering::Relaxed);
+        pending.take()
+    }
+
+    fn record_drop(&self) {
+        let dropped_messages = self.dropped_messages.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.congested.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Client {} is consuming too slowly, {} messages dropped so far",
+                self.client_id, dropped_messages
+            );
+        }
+    }
+}
+
+/** Pending messages in publish order. A conflated message is appended and leaves a hole where the
+message it replaced was, so sequence numbers keep increasing for clients that resume */
+#[derive(Default)]
+struct PendingQueue {
+    slots: VecDeque<Option<PendingSlot>>,
+    front_id: u64,
+    live: usize,
+    latest_by_key: HashMap<Arc<str>, u64>,
+}
+
+struct PendingSlot {
+    message: BroadcastMessage,
+    /// Pushed with `push_snapshot`, kept until drained
+    pinned: bool,
+}
+
+impl PendingQueue {
+    fn len(&self) -> usize {
+        self.live
+    }
+
+    fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    fn has_key(&self, key: &str) -> bool {
+        self.latest_by_key.contains_key(key)
+    }
+
+    fn push_back(&mut self, message: BroadcastMessage, pinned: bool) {
+        let id = self.front_id + self.slots.len() as u64;
+        let replaced_id = message
+            .shared_conflation_key()
+            .and_then(|key| self.latest_by_key.insert(key.clone(), id));
+        match replaced_id {
+            Some(replaced_id) => self.slots[(replaced_id - self.front_id) as usize] = None,
+            None => self.live += 1,
+        }
+        self.slots.push_back(Some(PendingSlot { message, pinned }));
+        if self.slots.len() > 2 * self.live.max(8) {
+            let slots = self.take_slots();
+            slots
+                .into_iter()
+                .for_each(|slot| self.push_back(slot.message, slot.pinned));
+        }
+    }
+
+    /// Evicts the oldest message that is not pinned, returns false when there is none
+    fn evict_oldest(&mut self) -> bool {
+        let position = self
+            .slots
+            .iter()
+            .position(|slot| slot.as_ref().map_or(false, |slot| !slot.pinned));
+        let evicted = match position.and_then(|position| self.slots[position].take()) {
+            Some(evicted) => evicted,
+            None => return false,
+        };
+        if let Some(key) = evicted.message.shared_conflation_key() {
+            self.latest_by_key.remove(key);
+        }
+        self.live -= 1;
+        while let Some(None) = self.slots.front() {
+            self.slots.pop_front();
+            self.front_id += 1;
+        }
+        true
+    }
+
+    fn take(&mut self) -> Vec<BroadcastMessage> {
+        self.take_slots().into_iter().map(|slot| slot.message).collect()
+    }
+
+    fn take_slots(&mut self) -> Vec<PendingSlot> {
+        self.front_id += self.slots.len() as u64;
+        self.live = 0;
+        self.latest_by_key.clear();
+        self.slots.drain(..).flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::common_types::MessagePayload;
+
+    fn message(text: &str) -> BroadcastMessage {
+        BroadcastMessage::from(MessagePayload::from(text))
+    }
+
+    fn keyed_message(key: &str, text: &str) -> BroadcastMessage {
+        message(text).with_conflation_key(key)
+    }
+
+    fn pending_texts(outbox: &ClientOutbox) -> Vec<String> {
+        outbox
+            .drain()
+            .into_iter()
+            .filter_map(|message| message.payload().as_text().map(str::to_owned))
+            .collect()
+    }
+
+    #[test]
+    fn test_first_message_wakes_the_client() {
+        let outbox = ClientOutbox::new(2, SlowConsumerPolicy::DropNewest);
+        assert_eq!(outbox.push(message("a")), PushOutcome::Wake);
+        assert_eq!(outbox.push(message("b")), PushOutcome::Queued);
+        assert_eq!(pending_texts(&outbox), vec!["a", "b"]);
+        assert_eq!(outbox.push(message("c")), PushOutcome::Wake);
+    }
+
+    #[test]
+    fn test_drop_newest_keeps_pending_messages() {
+        let outbox = ClientOutbox::new(2, SlowConsumerPolicy::DropNewest);
+        outbox.push(message("a"));
+        outbox.push(message("b"));
+        assert_eq!(outbox.push(message("c")), PushOutcome::Dropped);
+        assert_eq!(outbox.dropped_messages(), 1);
+        assert_eq!(pending_texts(&outbox), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_drop_oldest_evicts_front_message() {
+        let outbox = ClientOutbox::new(2, SlowConsumerPolicy::DropOldest);
+        outbox.push(message("a"));
+        outbox.push(message("b"));
+        assert_eq!(outbox.push(message("c")), PushOutcome::Dropped);
+        assert_eq!(outbox.dropped_messages(), 1);
+        assert_eq!(pending_texts(&outbox), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_snapshot_is_queued_even_when_full() {
+        let outbox = ClientOutbox::new(1, SlowConsumerPolicy::DropNewest);
+        assert!(outbox.push_snapshot(message("snapshot")));
+        assert_eq!(outbox.push(message("a")), PushOutcome::Dropped);
+        assert!(!outbox.push_snapshot(message("snapshot")));
+        assert_eq!(pending_texts(&outbox), vec!["snapshot", "snapshot"]);
+    }
+
+    #[test]
+    fn test_drop_oldest_never_evicts_snapshots() {
+        let outbox = ClientOutbox::new(2, SlowConsumerPolicy::DropOldest);
+        assert!(outbox.push_snapshot(message("snapshot")));
+        assert_eq!(outbox.push(message("a")), PushOutcome::Queued);
+        assert_eq!(outbox.push(message("b")), PushOutcome::Dropped);
+        assert_eq!(outbox.push(message("c")), PushOutcome::Dropped);
+        assert_eq!(pending_texts(&outbox), vec!["snapshot", "c"]);
+        outbox.push_snapshot(message("resume"));
+        outbox.push_snapshot(message("replayed"));
+        assert_eq!(outbox.push(message("d")), PushOutcome::Dropped);
+        assert_eq!(outbox.dropped_messages(), 3);
+        assert_eq!(pending_texts(&outbox), vec!["resume", "replayed"]);
+    }
+
+    #[test]
+    fn test_disconnect_after_max_overflows() {
+        let policy = SlowConsumerPolicy::Disconnect {
+            close_code: 4008,
+            max_overflows: 2,
+        };
+        let outbox = ClientOutbox::new(1, policy);
+        outbox.push(message("a"));
+        assert_eq!(outbox.push(message("b")), PushOutcome::Dropped);
+        assert_eq!(outbox.push(message("c")), PushOutcome::Disconnect(4008));
+        assert_eq!(outbox.dropped_messages(), 2);
+    }
+
+    #[test]
+    fn test_conflation_keeps_newest_message_per_key() {
+        let outbox = ClientOutbox::new(3, SlowConsumerPolicy::DropNewest);
+        assert_eq!(outbox.push(keyed_message("BTC", "btc 1")), PushOutcome::Wake);
+        assert_eq!(outbox.push(keyed_message("ETH", "eth 1")), PushOutcome::Queued);
+        assert_eq!(outbox.push(message("trade")), PushOutcome::Queued);
+        assert_eq!(outbox.push(keyed_message("BTC", "btc 2")), PushOutcome::Conflated);
+        assert_eq!(outbox.push(keyed_message("BTC", "btc 3")), PushOutcome::Conflated);
+        assert_eq!(outbox.push(keyed_message("SOL", "sol 1")), PushOutcome::Dropped);
+        assert_eq!(pending_texts(&outbox), vec!["eth 1", "trade", "btc 3"]);
+        assert_eq!(outbox.push(keyed_message("BTC", "btc 4")), PushOutcome::Wake);
+    }
+
+    #[test]
+    fn test_conflation_survives_eviction_and_compaction() {
+        let outbox = ClientOutbox::new(2, SlowConsumerPolicy::DropOldest);
+        outbox.push(keyed_message("BTC", "btc 1"));
+        outbox.push(keyed_message("ETH", "eth 1"));
+        assert_eq!(outbox.push(message("trade")), PushOutcome::Dropped);
+        assert_eq!(outbox.push(keyed_message("BTC", "btc 2")), PushOutcome::Dropped);
+        for price in 0..100 {
+            assert_eq!(
+                outbox.push(keyed_message("BTC", &format!("btc {}", price))),
+                PushOutcome::Conflated
+            );
+        }
+        assert_eq!(pending_texts(&outbox), vec!["trade", "btc 99"]);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::actix_web_actors::ws::Message as WsMessage;
+use crate::bytes::BufMut;
+use crate::bytes::Bytes;
+use crate::bytes::BytesMut;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json;
@@ -24,6 +28,93 @@ pub enum WebsocketServiceType {
     Reactive,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessagePayload {
+    Text(String),
+    Binary(Bytes),
+}
+
+impl MessagePayload {
+    pub fn is_binary(&self) -> bool {
+        match self {
+            Self::Text(_) => false,
+            Self::Binary(_) => true,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            Self::Binary(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(bytes) => bytes.as_ref(),
+        }
+    }
+
+    /// Unmasked server websocket frame holding the whole payload, ready to be written to any client
+    pub fn encode_frame(&self) -> Bytes {
+        let (opcode, payload) = match self {
+            Self::Text(text) => (0x1, text.as_bytes()),
+            Self::Binary(bytes) => (0x2, bytes.as_ref()),
+        };
+        let mut frame = BytesMut::with_capacity(payload.len() + 10);
+        frame.put_u8(0x80 | opcode);
+        if payload.len() < 126 {
+            frame.put_u8(payload.len() as u8);
+        } else if payload.len() <= std::u16::MAX as usize {
+            frame.put_u8(126);
+            frame.put_u16_be(payload.len() as u16);
+        } else {
+            frame.put_u8(127);
+            frame.put_u64_be(payload.len() as u64);
+        }
+        frame.put_slice(payload);
+        frame.freeze()
+    }
+
+    pub(crate) fn into_ws_message(self) -> WsMessage {
+        match self {
+            Self::Text(text) => WsMessage::Text(text),
+            Self::Binary(bytes) => WsMessage::Binary(bytes),
+        }
+    }
+}
+
+impl From<String> for MessagePayload {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for MessagePayload {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_owned())
+    }
+}
+
+impl From<Bytes> for MessagePayload {
+    fn from(bytes: Bytes) -> Self {
+        Self::Binary(bytes)
+    }
+}
+
+impl From<Vec<u8>> for MessagePayload {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Binary(Bytes::from(bytes))
+    }
+}
+
+/// Text frames starting with `ping` in any case are answered with `pong`
+pub(crate) fn is_ping(text: &str) -> bool {
+    text.get(..4)
+        .map_or(false, |prefix| prefix.eq_ignore_ascii_case("ping"))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CommonResponse {
     pub error: Vec<String>,
@@ -70,4 +161,34 @@ mod unit_tests {
         assert!(reconstructed_response.error.contains(&ERROR_MESSAGE.to_owned()));
         assert!(&reconstructed_response as *const _ != &original_response as *const _);
     }
+
+    #[test]
+    fn test_message_payload_conversions() {
+        let text_payload = MessagePayload::from("love");
+        assert!(!text_payload.is_binary());
+        assert_eq!(text_payload.as_text(), Some("love"));
+        let binary_payload = MessagePayload::from(vec![0x6c, 0x6f, 0x76, 0x65]);
+        assert!(binary_payload.is_binary());
+        assert_eq!(binary_payload.as_text(), None);
+        assert_eq!(binary_payload.as_bytes(), text_payload.as_bytes());
+    }
+
+    #[test]
+    fn test_ping_detection() {
+        assert!(is_ping("PING"));
+        assert!(is_ping("ping 42"));
+        assert!(!is_ping("pin"));
+        assert!(!is_ping("aéé"));
+    }
+
+    #[test]
+    fn test_encoded_frame_headers() {
+        assert_eq!(&MessagePayload::from("love").encode_frame()[..], b"\x81\x04love");
+        let frame = MessagePayload::from(vec![7; 300]).encode_frame();
+        assert_eq!(&frame[..4], &[0x82, 126, 0x01, 0x2c]);
+        assert_eq!(frame.len(), 304);
+        let frame = MessagePayload::from(vec![7; 70_000]).encode_frame();
+        assert_eq!(&frame[..10], &[0x82, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]);
+        assert_eq!(frame.len(), 70_010);
+    }
 }
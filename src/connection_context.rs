@@ -0,0 +1,52 @@
+use crate::actix_web::http::HeaderMap;
+use crate::uuid::Uuid;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Everything known about a reactive connection, captured at upgrade and kept for its whole lifetime
+pub struct ConnectionContext {
+    connection_id: Uuid,
+    remote_address: Option<SocketAddr>,
+    headers: HeaderMap,
+    claims: Option<Value>,
+    session: HashMap<String, Value>,
+}
+
+impl ConnectionContext {
+    pub(crate) fn new(remote_address: Option<SocketAddr>, headers: HeaderMap, claims: Option<Value>) -> Self {
+        Self {
+            connection_id: Uuid::new_v4(),
+            remote_address,
+            headers,
+            claims,
+            session: HashMap::new(),
+        }
+    }
+
+    pub fn connection_id(&self) -> Uuid {
+        self.connection_id
+    }
+
+    pub fn remote_address(&self) -> Option<SocketAddr> {
+        self.remote_address
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The decoded JWT claims, `None` when the service runs without authentication
+    pub fn claims(&self) -> Option<&Value> {
+        self.claims.as_ref()
+    }
+
+    pub fn session(&self) -> &HashMap<String, Value> {
+        &self.session
+    }
+
+    /// Survives across messages and is dropped when the connection closes
+    pub fn session_mut(&mut self) -> &mut HashMap<String, Value> {
+        &mut self.session
+    }
+}
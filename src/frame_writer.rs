@@ -0,0 +1,146 @@
+use crate::actix::Actor as ActixActor;
+use crate::actix::StreamHandler;
+use crate::actix_web::error::PayloadError;
+use crate::actix_web::Error as HttpError;
+use crate::actix_web::HttpRequest;
+use crate::actix_web::HttpResponse;
+use crate::actix_web_actors::ws::handshake;
+use crate::actix_web_actors::ws::Message as WsMessage;
+use crate::actix_web_actors::ws::ProtocolError as WsProtocolError;
+use crate::actix_web_actors::ws::WebsocketContext;
+use crate::bytes::Bytes;
+use crate::futures::prelude::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/** Queue of websocket frames encoded ahead of time, written by an actor next to its own context.
+The frames are handed to the socket as they are, so one encoded frame is shared by every client it is sent to */
+#[derive(Clone, Default)]
+pub(crate) struct FrameWriter(Rc<RefCell<FrameQueue>>);
+
+#[derive(Default)]
+struct FrameQueue {
+    frames: VecDeque<Bytes>,
+    closed: bool,
+}
+
+impl FrameWriter {
+    /// Ignored once the writer is closed
+    pub(crate) fn write(&self, frame: Bytes) {
+        let mut queue = self.0.borrow_mut();
+        if !queue.closed {
+            queue.frames.push_back(frame);
+        }
+    }
+
+    /** Called when the actor is stopping, after its close frame. Frames already queued still go out ahead of it,
+    later ones are dropped since RFC 6455 forbids data after a close */
+    pub(crate) fn close(&self) {
+        self.0.borrow_mut().closed = true;
+    }
+
+    fn next(&self) -> Option<Bytes> {
+        self.0.borrow_mut().frames.pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.borrow().frames.is_empty()
+    }
+
+    fn close_and_clear(&self) {
+        let mut queue = self.0.borrow_mut();
+        queue.closed = true;
+        queue.frames.clear();
+    }
+}
+
+/** Response body of a websocket actor writing through a `FrameWriter`. Frames written while the context
+is polled go out ahead of what the actor wrote to the context in the same poll, so a close comes last.
+Frames written after the writer is closed, or still queued once the context is done, are dropped */
+pub(crate) struct FramedBody<S> {
+    context: S,
+    frames: FrameWriter,
+    pending: Option<Bytes>,
+}
+
+impl<S> FramedBody<S> {
+    pub(crate) fn new(context: S, frames: FrameWriter) -> Self {
+        Self {
+            context,
+            frames,
+            pending: None,
+        }
+    }
+}
+
+impl<S: Stream<Item = Bytes>> Stream for FramedBody<S> {
+    type Item = Bytes;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, S::Error> {
+        loop {
+            if let Some(frame) = self.frames.next() {
+                return Ok(Async::Ready(Some(frame)));
+            }
+            if let Some(chunk) = self.pending.take() {
+                return Ok(Async::Ready(Some(chunk)));
+            }
+            match self.context.poll()? {
+                Async::Ready(Some(chunk)) => self.pending = Some(chunk),
+                Async::Ready(None) => {
+                    self.frames.close_and_clear();
+                    return Ok(Async::Ready(None));
+                }
+                Async::NotReady if self.frames.is_empty() => return Ok(Async::NotReady),
+                Async::NotReady => (),
+            }
+        }
+    }
+}
+
+/// Same as `ws::start`, with `frames` written to the socket alongside the context of `actor`
+pub(crate) fn start_with_frames<A, T>(
+    actor: A,
+    frames: FrameWriter,
+    request: &HttpRequest,
+    stream: T,
+) -> Result<HttpResponse, HttpError>
+where
+    A: ActixActor<Context = WebsocketContext<A>> + StreamHandler<WsMessage, WsProtocolError>,
+    T: Stream<Item = Bytes, Error = PayloadError> + 'static,
+{
+    let mut response = handshake(request)?;
+    Ok(response.streaming(FramedBody::new(WebsocketContext::create(actor, stream), frames)))
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::futures::future::lazy;
+    use crate::futures::stream::iter_ok;
+
+    #[test]
+    fn test_frames_go_out_before_the_context_chunk_of_the_same_poll() {
+        let frames = FrameWriter::default();
+        let writer = frames.clone();
+        let context = iter_ok::<_, ()>(vec![Bytes::from("first"), Bytes::from("close")]).map(move |chunk| {
+            if chunk == "close" {
+                writer.write(Bytes::from("frame"));
+                writer.close();
+            }
+            chunk
+        });
+        let mut body = FramedBody::new(context, frames.clone());
+        lazy(move || {
+            assert_eq!(body.poll(), Ok(Async::Ready(Some(Bytes::from("first")))));
+            assert_eq!(body.poll(), Ok(Async::Ready(Some(Bytes::from("frame")))));
+            assert_eq!(body.poll(), Ok(Async::Ready(Some(Bytes::from("close")))));
+            frames.write(Bytes::from("late"));
+            assert_eq!(body.poll(), Ok(Async::Ready(None)));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use crate::error;
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io::Error as IOError;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+
+const HANDLER_PANICKED_MESSAGE: &str = "Internal error";
+
+/** Failure of a reactive handler, answered with a `CommonResponse` error for that request,
+or of a fallible periodic getter */
+#[derive(Debug, PartialEq)]
+pub struct HandlerError {
+    message: String,
+}
+
+impl HandlerError {
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    /// Any error, with its `Display` text as the message
+    pub fn from_error<E: Error>(error: E) -> Self {
+        Self::new(error.to_string())
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.message)
+    }
+}
+
+impl Error for HandlerError {}
+
+impl From<serde_json::Error> for HandlerError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::from_error(error)
+    }
+}
+
+impl From<IOError> for HandlerError {
+    fn from(error: IOError) -> Self {
+        Self::from_error(error)
+    }
+}
+
+/** Runs a handler call, turning a panic into a `HandlerError` when `catch_panics` is set. The panic itself is
+reported to Sentry by the panic handler registered in `init_log` */
+pub(crate) fn call_handler<R, F>(catch_panics: bool, call: F) -> Result<R, HandlerError>
+where
+    F: FnOnce() -> R,
+{
+    if !catch_panics {
+        return Ok(call());
+    }
+    catch_unwind(AssertUnwindSafe(call)).map_err(|panic| handler_panicked(&*panic))
+}
+
+pub(crate) fn handler_panicked(panic: &(dyn Any + Send)) -> HandlerError {
+    let details = panic
+        .downcast_ref::<&str>()
+        .map(|details| (*details).to_owned())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    error!("Reactive handler panicked: {}", details);
+    HandlerError::new(HANDLER_PANICKED_MESSAGE)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_convert_into_handler_error() {
+        let parse = |text: &str| -> Result<u64, HandlerError> { text.parse::<u64>().map_err(HandlerError::from_error) };
+        assert_eq!(parse("42"), Ok(42));
+        assert_eq!(
+            parse("forty-two"),
+            Err(HandlerError::new("invalid digit found in string"))
+        );
+        let parse_json = |text: &str| -> Result<u64, HandlerError> { Ok(serde_json::from_str::<u64>(text)?) };
+        assert_eq!(parse_json("42"), Ok(42));
+        assert!(parse_json("forty-two").is_err());
+        let source: Box<dyn Error> = Box::new(HandlerError::new("cache unavailable"));
+        assert_eq!(source.to_string(), "cache unavailable");
+    }
+
+    #[test]
+    fn test_caught_panic_becomes_handler_error() {
+        assert_eq!(call_handler(true, || 1), Ok(1));
+        assert_eq!(
+            call_handler(true, || -> u64 { panic!("order book missing") }),
+            Err(HandlerError::new(HANDLER_PANICKED_MESSAGE))
+        );
+    }
+}
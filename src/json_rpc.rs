@@ -0,0 +1,271 @@
+use crate::common_types::MessagePayload;
+use crate::connection_context::ConnectionContext;
+use crate::handler_error::call_handler;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+
+type JsonRpcMethod = Box<dyn Fn(&mut ConnectionContext, Value) -> Result<Value, JsonRpcError> + Send + Sync>;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn new<M: Into<String>>(code: i64, message: M) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn parse_error() -> Self {
+        Self::new(Self::PARSE_ERROR, "Parse error")
+    }
+
+    pub fn invalid_request() -> Self {
+        Self::new(Self::INVALID_REQUEST, "Invalid Request")
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(Self::METHOD_NOT_FOUND, "Method not found").with_data(Value::from(method))
+    }
+
+    pub fn invalid_params<D: ToString>(details: D) -> Self {
+        Self::new(Self::INVALID_PARAMS, "Invalid params").with_data(Value::from(details.to_string()))
+    }
+
+    pub fn internal_error<D: ToString>(details: D) -> Self {
+        Self::new(Self::INTERNAL_ERROR, "Internal error").with_data(Value::from(details.to_string()))
+    }
+}
+
+/** JSON-RPC 2.0 methods served by a reactive service through `MessageHandler::JsonRpc`.
+Requests without an `id` are notifications and never get a response, batches are answered with an array */
+#[derive(Default)]
+pub struct JsonRpcRegistry {
+    methods: HashMap<String, JsonRpcMethod>,
+}
+
+impl JsonRpcRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Absent params are deserialized from `null`, params that fail to deserialize are answered with invalid params
+    pub fn method<P, R, F>(mut self, name: &str, handler: F) -> Self
+    where
+        P: DeserializeOwned + 'static,
+        R: Serialize + 'static,
+        F: Fn(&mut ConnectionContext, P) -> Result<R, JsonRpcError> + Send + Sync + 'static,
+    {
+        let method = move |connection: &mut ConnectionContext, params: Value| {
+            let params = serde_json::from_value::<P>(params).map_err(JsonRpcError::invalid_params)?;
+            let result = handler(connection, params)?;
+            serde_json::to_value(result).map_err(JsonRpcError::internal_error)
+        };
+        self.methods.insert(name.to_owned(), Box::new(method));
+        self
+    }
+
+    pub fn handle(&self, connection: &mut ConnectionContext, payload: &MessagePayload) -> Option<MessagePayload> {
+        self.handle_with(false, connection, payload)
+    }
+
+    /// With `catch_panics` a panicking method is answered with an internal error carrying the request id
+    pub(crate) fn handle_with(
+        &self,
+        catch_panics: bool,
+        connection: &mut ConnectionContext,
+        payload: &MessagePayload,
+    ) -> Option<MessagePayload> {
+        let response = match serde_json::from_slice::<Value>(payload.as_bytes()) {
+            Err(_) => Some(error_response(Value::Null, JsonRpcError::parse_error())),
+            Ok(Value::Array(batch)) if batch.is_empty() => {
+                Some(error_response(Value::Null, JsonRpcError::invalid_request()))
+            }
+            Ok(Value::Array(batch)) => {
+                let responses = batch
+                    .into_iter()
+                    .filter_map(|request| self.call(catch_panics, connection, request))
+                    .collect::<Vec<_>>();
+                if responses.is_empty() {
+                    None
+                } else {
+                    Some(Value::Array(responses))
+                }
+            }
+            Ok(request) => self.call(catch_panics, connection, request),
+        };
+        response.map(|response| MessagePayload::Text(response.to_string()))
+    }
+
+    fn call(&self, catch_panics: bool, connection: &mut ConnectionContext, request: Value) -> Option<Value> {
+        let mut request = match request {
+            Value::Object(request) => request,
+            _ => return Some(error_response(Value::Null, JsonRpcError::invalid_request())),
+        };
+        let id = request.remove("id");
+        let valid_id = match &id {
+            None | Some(Value::Null) | Some(Value::Number(_)) | Some(Value::String(_)) => true,
+            _ => false,
+        };
+        let valid_params = match request.get("params") {
+            None | Some(Value::Array(_)) | Some(Value::Object(_)) => true,
+            _ => false,
+        };
+        let valid_version = request.get("jsonrpc") == Some(&json!("2.0"));
+        let method = match request.remove("method") {
+            Some(Value::String(method)) if valid_id && valid_params && valid_version => method,
+            _ => {
+                let id = id.filter(|_| valid_id).unwrap_or(Value::Null);
+                return Some(error_response(id, JsonRpcError::invalid_request()));
+            }
+        };
+        let params = request.remove("params").unwrap_or(Value::Null);
+        let result = match self.methods.get(&method) {
+            Some(handler) => call_handler(catch_panics, || handler(connection, params))
+                .unwrap_or_else(|handler_error| Err(JsonRpcError::internal_error(handler_error))),
+            None => Err(JsonRpcError::method_not_found(&method)),
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+            Err(error) => error_response(id, error),
+        })
+    }
+}
+
+fn error_response(id: Value, error: JsonRpcError) -> Value {
+    json!({"jsonrpc": "2.0", "error": error, "id": id})
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::actix_web::http::HeaderMap;
+
+    fn registry() -> JsonRpcRegistry {
+        JsonRpcRegistry::new()
+            .method("subtract", |_, (minuend, subtrahend): (i64, i64)| {
+                Ok(minuend - subtrahend)
+            })
+            .method("get_balance", |_, _: Value| {
+                Err::<Value, _>(JsonRpcError::new(-32001, "Account locked"))
+            })
+            .method("remember", |connection: &mut ConnectionContext, value: Value| {
+                Ok(connection.session_mut().insert("remembered".to_owned(), value))
+            })
+            .method("crash", |_, _: Value| -> Result<Value, JsonRpcError> {
+                panic!("order book missing")
+            })
+    }
+
+    fn call(request: &str) -> Option<Value> {
+        let mut connection = ConnectionContext::new(None, HeaderMap::new(), None);
+        registry()
+            .handle(&mut connection, &MessagePayload::from(request))
+            .map(|response| serde_json::from_str(response.as_text().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_request_is_correlated_by_id() {
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","method":"subtract","params":[42,23],"id":"a"}"#),
+            Some(json!({"jsonrpc": "2.0", "result": 19, "id": "a"}))
+        );
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","method":"get_balance","id":7}"#),
+            Some(json!({"jsonrpc": "2.0", "error": {"code": -32001, "message": "Account locked"}, "id": 7}))
+        );
+        assert_eq!(call(r#"{"jsonrpc":"2.0","method":"subtract","params":[1,2]}"#), None);
+    }
+
+    #[test]
+    fn test_session_survives_across_requests() {
+        let registry = registry();
+        let mut connection = ConnectionContext::new(None, HeaderMap::new(), None);
+        let remember = |connection: &mut ConnectionContext, value: &str| {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","method":"remember","params":{{"v":"{}"}},"id":1}}"#,
+                value
+            );
+            let response = registry.handle(connection, &MessagePayload::from(request)).unwrap();
+            serde_json::from_str::<Value>(response.as_text().unwrap()).unwrap()["result"].clone()
+        };
+        assert_eq!(remember(&mut connection, "a"), Value::Null);
+        assert_eq!(remember(&mut connection, "b"), json!({"v": "a"}));
+        assert_eq!(connection.session().get("remembered"), Some(&json!({"v": "b"})));
+    }
+
+    #[test]
+    fn test_standard_errors() {
+        let error_code = |request: &str| call(request).unwrap()["error"]["code"].as_i64().unwrap();
+        assert_eq!(error_code(r#"{"jsonrpc":"2.0","method""#), JsonRpcError::PARSE_ERROR);
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"1.0","method":"subtract","id":1}"#),
+            JsonRpcError::INVALID_REQUEST
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":1,"id":1}"#),
+            JsonRpcError::INVALID_REQUEST
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"divide","id":1}"#),
+            JsonRpcError::METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc":"2.0","method":"subtract","params":["a"],"id":1}"#),
+            JsonRpcError::INVALID_PARAMS
+        );
+        assert_eq!(error_code("[]"), JsonRpcError::INVALID_REQUEST);
+        let response = call(r#"{"jsonrpc":"2.0","method":"subtract","id":{"a":1}}"#).unwrap();
+        assert_eq!(response["error"]["code"], json!(JsonRpcError::INVALID_REQUEST));
+        assert_eq!(response["id"], Value::Null);
+    }
+
+    #[test]
+    fn test_caught_panic_is_an_internal_error() {
+        let mut connection = ConnectionContext::new(None, HeaderMap::new(), None);
+        let request = MessagePayload::from(r#"{"jsonrpc":"2.0","method":"crash","id":"b"}"#);
+        let response = registry().handle_with(true, &mut connection, &request).unwrap();
+        let response = serde_json::from_str::<Value>(response.as_text().unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], json!(JsonRpcError::INTERNAL_ERROR));
+        assert_eq!(response["id"], json!("b"));
+    }
+
+    #[test]
+    fn test_batch_skips_notifications() {
+        let response = call(
+            r#"[{"jsonrpc":"2.0","method":"subtract","params":[3,1],"id":1},
+                {"jsonrpc":"2.0","method":"subtract","params":[3,1]},
+                1]"#,
+        );
+        assert_eq!(
+            response,
+            Some(json!([
+                {"jsonrpc": "2.0", "result": 2, "id": 1},
+                {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null}
+            ]))
+        );
+        assert_eq!(call(r#"[{"jsonrpc":"2.0","method":"subtract","params":[3,1]}]"#), None);
+    }
+}
@@ -5,6 +5,7 @@ pub extern crate actix_server;
 pub extern crate actix_web;
 pub extern crate actix_web_actors;
 pub extern crate biscuit;
+pub extern crate bytes;
 pub extern crate chrono;
 pub extern crate crossbeam_channel;
 pub extern crate crossbeam_utils;
@@ -20,24 +21,60 @@ pub extern crate uuid;
 mod auth;
 mod broadcast_periodic;
 mod broadcast_pubsub;
+mod client_outbox;
 mod common_types;
+mod connection_context;
 mod env_helper;
+mod frame_writer;
+mod handler_error;
+mod json_rpc;
+mod message_router;
+mod periodic_delta;
+mod pubsub_filter;
+mod rate_limiter;
 mod reactive;
+mod read_gate;
+mod replay_buffer;
+mod request_pipeline;
+mod service_handle;
+mod typed_handler;
 
 pub use auth::*;
-pub use broadcast_periodic::{run_periodic_websocket_service, PeriodicWebsocketConfig, PeriodicWebsocketState};
+pub use broadcast_periodic::{
+    run_periodic_websocket_service, start_periodic_websocket_service, FalliblePeriodicGetter, GetterFailureAction,
+    GetterFailureLimit, InfalliblePeriodicGetter, PeriodicMessageGetter, PeriodicParams, PeriodicWebsocketConfig,
+    PeriodicWebsocketState,
+};
+#[cfg(feature = "bench")]
+pub use broadcast_pubsub::fan_out_frames;
 pub use broadcast_pubsub::{
-    run_pubsub_websocket_service, BroadcastMessage, PubsubWebsocketConfig, PubsubWebsocketState, SendBroadcastFunction,
+    run_pubsub_websocket_service, start_pubsub_websocket_service, BroadcastMessage, ConflationKeyProvider,
+    PubsubWebsocketConfig, PubsubWebsocketState, SendBroadcastFunction, SendTopicBroadcastFunction, SnapshotProvider,
     StaticStateArc,
 };
+pub use client_outbox::SlowConsumerPolicy;
 pub use common_types::*;
+pub use connection_context::ConnectionContext;
 pub use env_helper::{
     get_env_bool, get_env_int, get_env_string, get_executable_name, get_mandatory_env_bool, get_mandatory_env_int,
     get_mandatory_env_string,
 };
+pub use handler_error::HandlerError;
+pub use json_rpc::{JsonRpcError, JsonRpcRegistry};
 pub use log::{debug, error, info, trace, warn};
-pub use reactive::{run_reactive_websocket_service, ReactiveWebsocketConfig, ReactiveWebsocketState};
+pub use message_router::MessageRouter;
+pub use periodic_delta::{DeltaUpdates, UnchangedAction};
+pub use pubsub_filter::{FilterError, SubscriptionFilter};
+pub use rate_limiter::{RateLimit, RateLimitAction};
+pub use reactive::{
+    run_reactive_websocket_service, start_reactive_websocket_service, AsyncMessageHandler, FallibleMessageHandler,
+    MessageHandler, ReactiveResponseFuture, ReactiveResponseStream, ReactiveWebsocketConfig, ReactiveWebsocketState,
+    StreamMessageHandler, SyncMessageHandler,
+};
+pub use request_pipeline::{InFlightLimit, InFlightLimitAction, ResponseOrdering};
 pub use sentry::internals::ClientInitGuard;
+pub use service_handle::ServiceHandle;
+pub use typed_handler::TypedHandler;
 
 use std::env;
 
@@ -0,0 +1,111 @@
+use crate::common_types::CommonResponse;
+use crate::common_types::JsonSerializable;
+use crate::common_types::MessagePayload;
+use crate::connection_context::ConnectionContext;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const DEFAULT_DISPATCH_FIELD: &str = "method";
+
+type RouteHandler = Box<dyn Fn(&mut ConnectionContext, MessagePayload) -> Option<MessagePayload> + Send + Sync>;
+
+/** Dispatches JSON messages to named handlers through `MessageHandler::Router`, on the value of
+`dispatch_field` (default `"method"`). Handlers get the whole message,
+unknown methods are answered with a `CommonResponse` error */
+pub struct MessageRouter {
+    dispatch_field: String,
+    routes: HashMap<String, RouteHandler>,
+}
+
+impl Default for MessageRouter {
+    fn default() -> Self {
+        Self {
+            dispatch_field: DEFAULT_DISPATCH_FIELD.to_owned(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl MessageRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dispatch_field(mut self, dispatch_field: &str) -> Self {
+        self.dispatch_field = dispatch_field.to_owned();
+        self
+    }
+
+    pub fn route<F>(mut self, method: &str, handler: F) -> Self
+    where
+        F: Fn(&mut ConnectionContext, MessagePayload) -> Option<MessagePayload> + Send + Sync + 'static,
+    {
+        self.routes.insert(method.to_owned(), Box::new(handler));
+        self
+    }
+
+    pub fn handle(&self, connection: &mut ConnectionContext, payload: MessagePayload) -> Option<MessagePayload> {
+        let message = serde_json::from_slice::<Value>(payload.as_bytes()).ok();
+        let method = message
+            .as_ref()
+            .and_then(|message| message.get(&self.dispatch_field))
+            .and_then(Value::as_str);
+        let mut response = CommonResponse::default();
+        match method.map(|method| (method, self.routes.get(method))) {
+            Some((_, Some(handler))) => return handler(connection, payload),
+            Some((method, None)) => response.error.push(format!("Unknown method '{}'", method)),
+            None => response.error.push(format!(
+                "Invalid request, expected a string '{}' field",
+                self.dispatch_field
+            )),
+        }
+        Some(MessagePayload::Text(response.to_json()))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::actix_web::http::HeaderMap;
+
+    fn dispatch(router: &MessageRouter, request: &str) -> Option<String> {
+        let mut connection = ConnectionContext::new(None, HeaderMap::new(), None);
+        router
+            .handle(&mut connection, MessagePayload::from(request))
+            .and_then(|response| response.as_text().map(str::to_owned))
+    }
+
+    fn error_of(response: Option<String>) -> Vec<String> {
+        CommonResponse::from_json(&response.unwrap()).unwrap().error
+    }
+
+    #[test]
+    fn test_dispatch_on_method_field() {
+        let router = MessageRouter::new()
+            .route("get_balance", |_, _| Some("balance".into()))
+            .route("get_orders", |_, payload| Some(payload));
+        assert_eq!(
+            dispatch(&router, r#"{"method":"get_balance"}"#),
+            Some("balance".to_owned())
+        );
+        assert_eq!(
+            dispatch(&router, r#"{"method":"get_orders","limit":5}"#),
+            Some(r#"{"method":"get_orders","limit":5}"#.to_owned())
+        );
+        assert_eq!(
+            error_of(dispatch(&router, r#"{"method":"withdraw"}"#)),
+            vec!["Unknown method 'withdraw'"]
+        );
+    }
+
+    #[test]
+    fn test_custom_dispatch_field() {
+        let router = MessageRouter::new().dispatch_field("op").route("ping", |_, _| None);
+        assert_eq!(dispatch(&router, r#"{"op":"ping"}"#), None);
+        assert_eq!(
+            error_of(dispatch(&router, r#"{"method":"ping"}"#)),
+            vec!["Invalid request, expected a string 'op' field"]
+        );
+        assert_eq!(error_of(dispatch(&router, "ping")).len(), 1);
+    }
+}
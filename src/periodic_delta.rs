@@ -0,0 +1,251 @@
+use crate::bytes::Bytes;
+use crate::common_types::MessagePayload;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/** Periodic JSON documents are sent as `{"type":"patch","data":...}` frames holding an RFC 7386 merge patch against
+the last document the client received, with `{"type":"snapshot","data":...}` frames on the first update and every
+`snapshot_every` updates. Merge patches cannot carry `null` values, so a changed document holding one is sent as a
+snapshot. Only text payloads are read as JSON documents */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeltaUpdates {
+    pub snapshot_every: u32,
+    pub unchanged: UnchangedAction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnchangedAction {
+    /// Send nothing when the document did not change since the last update
+    Skip,
+    /// Send a `{"type":"heartbeat"}` frame instead
+    Heartbeat,
+}
+
+/// Last document sent to one client
+#[derive(Default)]
+pub(crate) struct DeltaState {
+    last_sent: Option<Arc<Value>>,
+    updates_since_snapshot: u32,
+}
+
+/// Documents and encoded frames of one tick, shared by the clients receiving the same payload
+#[derive(Default)]
+pub(crate) struct DeltaTick {
+    documents: HashMap<*const MessagePayload, Option<Arc<Value>>>,
+    frames: HashMap<(*const Value, *const Value), Option<Bytes>>,
+    /// Whether each document of `documents` can be sent as a merge patch
+    patchable: HashMap<*const Value, bool>,
+    /// Keeps the documents keyed in `frames` alive, so their addresses are not reused during the tick
+    previous_documents: Vec<Arc<Value>>,
+    heartbeat: Option<Bytes>,
+}
+
+impl DeltaState {
+    /// The frame replacing `payload` for this client, `None` when the update is skipped
+    pub(crate) fn next_frame(
+        &mut self,
+        delta_updates: &DeltaUpdates,
+        payload: &Arc<MessagePayload>,
+        payload_frame: &Bytes,
+        tick: &mut DeltaTick,
+    ) -> Option<Bytes> {
+        let document = match tick.document(payload) {
+            Some(document) => document,
+            // Payloads that are not JSON documents always go out in full
+            None => return Some(payload_frame.clone()),
+        };
+        let previous = match self.last_sent.replace(document.clone()) {
+            Some(previous) if self.updates_since_snapshot < delta_updates.snapshot_every => previous,
+            _ => {
+                self.updates_since_snapshot = 0;
+                return tick.frame(None, &document);
+            }
+        };
+        // A patch would read the nulls of the document as removed fields
+        if !tick.patchable(&document) && previous != document {
+            self.updates_since_snapshot = 0;
+            return tick.frame(None, &document);
+        }
+        self.updates_since_snapshot += 1;
+        match tick.frame(Some(&previous), &document) {
+            Some(frame) => Some(frame),
+            None => match delta_updates.unchanged {
+                UnchangedAction::Skip => None,
+                UnchangedAction::Heartbeat => Some(tick.heartbeat()),
+            },
+        }
+    }
+}
+
+impl DeltaTick {
+    fn document(&mut self, payload: &Arc<MessagePayload>) -> Option<Arc<Value>> {
+        self.documents
+            .entry(&**payload as *const MessagePayload)
+            .or_insert_with(|| {
+                payload
+                    .as_text()
+                    .and_then(|text| serde_json::from_str::<Value>(text).ok())
+                    .map(Arc::new)
+            })
+            .clone()
+    }
+
+    fn patchable(&mut self, document: &Arc<Value>) -> bool {
+        *self
+            .patchable
+            .entry(&**document as *const Value)
+            .or_insert_with(|| !contains_null(document))
+    }
+
+    /// A snapshot without `previous`, otherwise a patch or `None` when nothing changed
+    fn frame(&mut self, previous: Option<&Arc<Value>>, document: &Arc<Value>) -> Option<Bytes> {
+        let key = (
+            previous.map_or(std::ptr::null(), |previous| &**previous as *const Value),
+            &**document as *const Value,
+        );
+        if let Some(frame) = self.frames.get(&key) {
+            return frame.clone();
+        }
+        let frame = match previous {
+            None => Some(json!({"type": "snapshot", "data": &**document})),
+            Some(previous) if previous == document => None,
+            Some(previous) => Some(json!({"type": "patch", "data": merge_patch(previous, document)})),
+        };
+        let frame = frame.map(|frame| MessagePayload::Text(frame.to_string()).encode_frame());
+        self.previous_documents.extend(previous.cloned());
+        self.frames.insert(key, frame.clone());
+        frame
+    }
+
+    fn heartbeat(&mut self) -> Bytes {
+        self.heartbeat
+            .get_or_insert_with(|| MessagePayload::Text(json!({"type": "heartbeat"}).to_string()).encode_frame())
+            .clone()
+    }
+}
+
+fn contains_null(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Array(values) => values.iter().any(contains_null),
+        Value::Object(fields) => fields.values().any(contains_null),
+        _ => false,
+    }
+}
+
+/// RFC 7386 merge patch turning `previous` into `current`
+pub(crate) fn merge_patch(previous: &Value, current: &Value) -> Value {
+    let (previous, current) = match (previous, current) {
+        (Value::Object(previous), Value::Object(current)) => (previous, current),
+        _ => return current.clone(),
+    };
+    let mut patch = Map::new();
+    for (key, previous_value) in previous {
+        match current.get(key) {
+            None => {
+                patch.insert(key.clone(), Value::Null);
+            }
+            Some(current_value) if current_value != previous_value => {
+                patch.insert(key.clone(), merge_patch(previous_value, current_value));
+            }
+            Some(_) => (),
+        }
+    }
+    for (key, current_value) in current {
+        if !previous.contains_key(key) {
+            patch.insert(key.clone(), current_value.clone());
+        }
+    }
+    Value::Object(patch)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn apply_merge_patch(target: &mut Value, patch: &Value) {
+        let patch = match patch {
+            Value::Object(patch) => patch,
+            _ => {
+                *target = patch.clone();
+                return;
+            }
+        };
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        let target = target.as_object_mut().unwrap();
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_patch_turns_previous_into_current() {
+        let previous = json!({"bids": {"100": 5, "99": 2}, "asks": {"101": 1}, "sequence": 7});
+        let current = json!({"bids": {"100": 3, "98": 4}, "asks": {"101": 1}, "sequence": 8});
+        let patch = merge_patch(&previous, &current);
+        assert_eq!(patch, json!({"bids": {"100": 3, "99": null, "98": 4}, "sequence": 8}));
+        let mut patched = previous.clone();
+        apply_merge_patch(&mut patched, &patch);
+        assert_eq!(patched, current);
+        assert_eq!(merge_patch(&json!([1, 2]), &json!([1])), json!([1]));
+    }
+
+    #[test]
+    fn test_snapshot_patch_and_unchanged_updates() {
+        let delta_updates = DeltaUpdates {
+            snapshot_every: 2,
+            unchanged: UnchangedAction::Heartbeat,
+        };
+        let mut state = DeltaState::default();
+        let mut next = |document: &str| {
+            let payload = Arc::new(MessagePayload::from(document));
+            let payload_frame = payload.encode_frame();
+            state.next_frame(&delta_updates, &payload, &payload_frame, &mut DeltaTick::default())
+        };
+        let text_frame = |text: &str| Some(MessagePayload::from(text).encode_frame());
+        assert_eq!(next(r#"{"a":1}"#), text_frame(r#"{"data":{"a":1},"type":"snapshot"}"#));
+        assert_eq!(next(r#"{"a":2}"#), text_frame(r#"{"data":{"a":2},"type":"patch"}"#));
+        assert_eq!(next(r#"{"a":2}"#), text_frame(r#"{"type":"heartbeat"}"#));
+        assert_eq!(next(r#"{"a":2}"#), text_frame(r#"{"data":{"a":2},"type":"snapshot"}"#));
+        assert_eq!(next("love"), text_frame("love"));
+    }
+
+    #[test]
+    fn test_documents_with_nulls_and_binary_payloads() {
+        let delta_updates = DeltaUpdates {
+            snapshot_every: 10,
+            unchanged: UnchangedAction::Skip,
+        };
+        let mut state = DeltaState::default();
+        let mut next = |payload: MessagePayload| {
+            let payload = Arc::new(payload);
+            let payload_frame = payload.encode_frame();
+            state.next_frame(&delta_updates, &payload, &payload_frame, &mut DeltaTick::default())
+        };
+        let text_frame = |text: &str| Some(MessagePayload::from(text).encode_frame());
+        assert_eq!(
+            next(MessagePayload::from(r#"{"a":1,"b":2}"#)),
+            text_frame(r#"{"data":{"a":1,"b":2},"type":"snapshot"}"#)
+        );
+        assert_eq!(
+            next(MessagePayload::from(r#"{"a":1,"b":null}"#)),
+            text_frame(r#"{"data":{"a":1,"b":null},"type":"snapshot"}"#)
+        );
+        assert_eq!(next(MessagePayload::from(r#"{"a":1,"b":null}"#)), None);
+        assert_eq!(
+            next(MessagePayload::from(r#"{"a":2}"#)),
+            text_frame(r#"{"data":{"a":2,"b":null},"type":"patch"}"#)
+        );
+        let binary = MessagePayload::Binary(Bytes::from(r#"{"a":3}"#));
+        assert_eq!(next(binary.clone()), Some(binary.encode_frame()));
+    }
+}
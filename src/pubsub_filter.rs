@@ -0,0 +1,436 @@
+//! Subscription filters evaluated against JSON payload fields,
+//! e.g. `notional >= 10000 && symbol in ['BTC_USDT', 'ETH_USDT']`.
+//! Grammar: `expr := and ('||' and)*`, `and := unary ('&&' unary)*`,
+//! `unary := '!' unary | '(' expr ')' | path op literal | path 'in' '[' literal (',' literal)* ']'`
+
+use crate::common_types::MessagePayload;
+use serde_json::Value;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+const MAX_FILTER_LENGTH: usize = 512;
+const MAX_FILTER_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum FilterError {
+    Empty,
+    TooLong,
+    TooDeep,
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    InvalidNumber(String),
+    UnterminatedString,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(formatter, "Filter is empty"),
+            Self::TooLong => write!(formatter, "Filter is longer than {} characters", MAX_FILTER_LENGTH),
+            Self::TooDeep => write!(formatter, "Filter is nested deeper than {} levels", MAX_FILTER_DEPTH),
+            Self::UnexpectedEnd => write!(formatter, "Filter ended unexpectedly"),
+            Self::UnexpectedToken(token) => write!(formatter, "Unexpected '{}' in filter", token),
+            Self::InvalidNumber(number) => write!(formatter, "Invalid number '{}' in filter", number),
+            Self::UnterminatedString => write!(formatter, "Unterminated string in filter"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Literal {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Path(Vec<String>),
+    Literal(Literal),
+    Compare(Comparison),
+    In,
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+}
+
+#[derive(Debug)]
+enum FilterExpression {
+    Compare(Vec<String>, Comparison, Literal),
+    In(Vec<String>, Vec<Literal>),
+    Not(Box<FilterExpression>),
+    And(Box<FilterExpression>, Box<FilterExpression>),
+    Or(Box<FilterExpression>, Box<FilterExpression>),
+}
+
+#[derive(Debug)]
+pub struct SubscriptionFilter {
+    source: String,
+    expression: FilterExpression,
+}
+
+impl SubscriptionFilter {
+    pub fn parse(source: &str) -> Result<Self, FilterError> {
+        let source = source.trim();
+        if source.is_empty() {
+            return Err(FilterError::Empty);
+        }
+        if source.len() > MAX_FILTER_LENGTH {
+            return Err(FilterError::TooLong);
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        let expression = parser.parse_or()?;
+        match parser.tokens.get(parser.position) {
+            Some(token) => Err(FilterError::UnexpectedToken(format!("{:?}", token))),
+            None => Ok(Self {
+                source: source.to_owned(),
+                expression,
+            }),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, document: &Value) -> bool {
+        evaluate(&self.expression, document)
+    }
+
+    /// Binary and non JSON payloads never match a filter
+    pub fn matches_payload(&self, payload: &MessagePayload) -> bool {
+        parse_document(payload)
+            .map(|document| self.matches(&document))
+            .unwrap_or(false)
+    }
+}
+
+pub(crate) fn parse_document(payload: &MessagePayload) -> Option<Value> {
+    payload
+        .as_text()
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&character) = chars.peek() {
+        let token = match character {
+            ' ' | '\t' | '\n' | '\r' => {
+                chars.next();
+                continue;
+            }
+            '(' | ')' | '[' | ']' | ',' => {
+                chars.next();
+                match character {
+                    '(' => Token::OpenParen,
+                    ')' => Token::CloseParen,
+                    '[' => Token::OpenBracket,
+                    ']' => Token::CloseBracket,
+                    _ => Token::Comma,
+                }
+            }
+            '\'' | '"' => Token::Literal(Literal::Text(read_string(&mut chars)?)),
+            '0'..='9' | '-' => Token::Literal(Literal::Number(read_number(&mut chars)?)),
+            '=' | '!' | '>' | '<' | '&' | '|' => read_operator(&mut chars)?,
+            'a'..='z' | 'A'..='Z' | '_' => read_word(&mut chars),
+            other => return Err(FilterError::UnexpectedToken(other.to_string())),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn read_string(chars: &mut Peekable<Chars>) -> Result<String, FilterError> {
+    let quote = chars.next().unwrap();
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(escaped) => text.push(escaped),
+                None => return Err(FilterError::UnterminatedString),
+            },
+            Some(character) if character == quote => return Ok(text),
+            Some(character) => text.push(character),
+            None => return Err(FilterError::UnterminatedString),
+        }
+    }
+}
+
+fn read_number(chars: &mut Peekable<Chars>) -> Result<f64, FilterError> {
+    let mut number = String::new();
+    while let Some(&character) = chars.peek() {
+        if character.is_ascii_digit() || character == '.' || character == '-' || character == 'e' || character == 'E' {
+            number.push(character);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    number.parse::<f64>().map_err(|_| FilterError::InvalidNumber(number))
+}
+
+fn read_operator(chars: &mut Peekable<Chars>) -> Result<Token, FilterError> {
+    let first = chars.next().unwrap();
+    let followed_by_equal = chars.peek() == Some(&'=');
+    let token = match (first, followed_by_equal) {
+        ('=', true) => Token::Compare(Comparison::Equal),
+        ('!', true) => Token::Compare(Comparison::NotEqual),
+        ('>', true) => Token::Compare(Comparison::GreaterOrEqual),
+        ('<', true) => Token::Compare(Comparison::LessOrEqual),
+        ('>', false) => return Ok(Token::Compare(Comparison::Greater)),
+        ('<', false) => return Ok(Token::Compare(Comparison::Less)),
+        ('!', false) => return Ok(Token::Not),
+        ('&', _) if chars.peek() == Some(&'&') => Token::And,
+        ('|', _) if chars.peek() == Some(&'|') => Token::Or,
+        (other, _) => return Err(FilterError::UnexpectedToken(other.to_string())),
+    };
+    chars.next();
+    Ok(token)
+}
+
+fn read_word(chars: &mut Peekable<Chars>) -> Token {
+    let mut word = String::new();
+    while let Some(&character) = chars.peek() {
+        if character.is_ascii_alphanumeric() || character == '_' || character == '.' {
+            word.push(character);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    match word.as_str() {
+        "in" => Token::In,
+        "and" => Token::And,
+        "or" => Token::Or,
+        "not" => Token::Not,
+        "true" => Token::Literal(Literal::Bool(true)),
+        "false" => Token::Literal(Literal::Bool(false)),
+        "null" => Token::Literal(Literal::Null),
+        _ => Token::Path(word.split('.').map(str::to_owned).collect()),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<&Token, FilterError> {
+        let token = self.tokens.get(self.position).ok_or(FilterError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_is(&self, expected: &Token) -> bool {
+        self.tokens.get(self.position) == Some(expected)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), FilterError> {
+        match self.next()? {
+            token if *token == expected => Ok(()),
+            token => Err(FilterError::UnexpectedToken(format!("{:?}", token))),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<FilterExpression, FilterError> {
+        self.depth += 1;
+        if self.depth > MAX_FILTER_DEPTH {
+            return Err(FilterError::TooDeep);
+        }
+        let mut expression = self.parse_and()?;
+        while self.next_is(&Token::Or) {
+            self.position += 1;
+            expression = FilterExpression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        self.depth -= 1;
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpression, FilterError> {
+        let mut expression = self.parse_unary()?;
+        while self.next_is(&Token::And) {
+            self.position += 1;
+            expression = FilterExpression::And(Box::new(expression), Box::new(self.parse_unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_unary(&mut self) -> Result<FilterExpression, FilterError> {
+        match self.next()? {
+            Token::Not => {
+                self.depth += 1;
+                if self.depth > MAX_FILTER_DEPTH {
+                    return Err(FilterError::TooDeep);
+                }
+                let expression = FilterExpression::Not(Box::new(self.parse_unary()?));
+                self.depth -= 1;
+                Ok(expression)
+            }
+            Token::OpenParen => {
+                let expression = self.parse_or()?;
+                self.expect(Token::CloseParen)?;
+                Ok(expression)
+            }
+            Token::Path(path) => {
+                let path = path.clone();
+                match self.next()? {
+                    Token::Compare(comparison) => {
+                        let comparison = *comparison;
+                        Ok(FilterExpression::Compare(path, comparison, self.parse_literal()?))
+                    }
+                    Token::In => Ok(FilterExpression::In(path, self.parse_literal_list()?)),
+                    token => Err(FilterError::UnexpectedToken(format!("{:?}", token))),
+                }
+            }
+            token => Err(FilterError::UnexpectedToken(format!("{:?}", token))),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, FilterError> {
+        match self.next()? {
+            Token::Literal(literal) => Ok(literal.clone()),
+            token => Err(FilterError::UnexpectedToken(format!("{:?}", token))),
+        }
+    }
+
+    fn parse_literal_list(&mut self) -> Result<Vec<Literal>, FilterError> {
+        self.expect(Token::OpenBracket)?;
+        let mut literals = vec![self.parse_literal()?];
+        while self.next_is(&Token::Comma) {
+            self.position += 1;
+            literals.push(self.parse_literal()?);
+        }
+        self.expect(Token::CloseBracket)?;
+        Ok(literals)
+    }
+}
+
+fn evaluate(expression: &FilterExpression, document: &Value) -> bool {
+    match expression {
+        FilterExpression::Compare(path, comparison, literal) => compare(resolve(document, path), *comparison, literal),
+        FilterExpression::In(path, literals) => {
+            let value = resolve(document, path);
+            literals
+                .iter()
+                .any(|literal| compare(value, Comparison::Equal, literal))
+        }
+        FilterExpression::Not(inner) => !evaluate(inner, document),
+        FilterExpression::And(left, right) => evaluate(left, document) && evaluate(right, document),
+        FilterExpression::Or(left, right) => evaluate(left, document) || evaluate(right, document),
+    }
+}
+
+fn resolve<'a>(document: &'a Value, path: &[String]) -> &'a Value {
+    path.iter()
+        .try_fold(document, |value, field| value.get(field.as_str()))
+        .unwrap_or(&Value::Null)
+}
+
+/// Numeric strings are compared as numbers, prices are often sent as strings
+fn compare(value: &Value, comparison: Comparison, literal: &Literal) -> bool {
+    let ordering = match (value, literal) {
+        (Value::Null, Literal::Null) => Some(std::cmp::Ordering::Equal),
+        (Value::Bool(left), Literal::Bool(right)) => Some(left.cmp(right)),
+        (Value::Number(left), Literal::Number(right)) => left.as_f64().and_then(|left| left.partial_cmp(right)),
+        (Value::String(left), Literal::Number(right)) => {
+            left.parse::<f64>().ok().and_then(|left| left.partial_cmp(right))
+        }
+        (Value::String(left), Literal::Text(right)) => Some(left.as_str().cmp(right.as_str())),
+        _ => None,
+    };
+    match (ordering, comparison) {
+        (None, Comparison::NotEqual) => true,
+        (None, _) => false,
+        (Some(ordering), Comparison::Equal) => ordering == std::cmp::Ordering::Equal,
+        (Some(ordering), Comparison::NotEqual) => ordering != std::cmp::Ordering::Equal,
+        (Some(ordering), Comparison::Greater) => ordering == std::cmp::Ordering::Greater,
+        (Some(ordering), Comparison::GreaterOrEqual) => ordering != std::cmp::Ordering::Less,
+        (Some(ordering), Comparison::Less) => ordering == std::cmp::Ordering::Less,
+        (Some(ordering), Comparison::LessOrEqual) => ordering != std::cmp::Ordering::Greater,
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn matches(filter: &str, document: &str) -> bool {
+        let document = serde_json::from_str::<Value>(document).unwrap();
+        SubscriptionFilter::parse(filter).unwrap().matches(&document)
+    }
+
+    #[test]
+    fn test_comparisons_on_payload_fields() {
+        const TRADE: &str = r#"{"symbol":"BTC_USDT","price":"9500.5","qty":2,"maker":true,"meta":{"venue":"bitwyre"}}"#;
+        assert!(matches("price > 9000", TRADE));
+        assert!(!matches("price >= 10000", TRADE));
+        assert!(matches("qty == 2 && maker == true", TRADE));
+        assert!(matches("symbol == 'BTC_USDT'", TRADE));
+        assert!(matches("meta.venue == \"bitwyre\"", TRADE));
+        assert!(matches("missing == null", TRADE));
+        assert!(matches("missing != 1", TRADE));
+        assert!(!matches("missing > 1", TRADE));
+    }
+
+    #[test]
+    fn test_boolean_operators_and_sets() {
+        const TRADE: &str = r#"{"symbol":"ETH_USDT","qty":5}"#;
+        assert!(matches("symbol in ['BTC_USDT', 'ETH_USDT']", TRADE));
+        assert!(!matches("symbol in ['BTC_USDT']", TRADE));
+        assert!(matches("qty < 1 || symbol == 'ETH_USDT'", TRADE));
+        assert!(matches("!(qty < 1) and not symbol == 'BTC_USDT'", TRADE));
+        assert!(!matches("qty > 1 && (symbol == 'BTC_USDT' || qty > 10)", TRADE));
+    }
+
+    #[test]
+    fn test_invalid_filters_are_rejected() {
+        assert_eq!(SubscriptionFilter::parse("  ").unwrap_err(), FilterError::Empty);
+        assert_eq!(
+            SubscriptionFilter::parse("price >").unwrap_err(),
+            FilterError::UnexpectedEnd
+        );
+        assert_eq!(
+            SubscriptionFilter::parse("symbol == 'BTC").unwrap_err(),
+            FilterError::UnterminatedString
+        );
+        assert!(SubscriptionFilter::parse("price > 1 price").is_err());
+        assert!(SubscriptionFilter::parse("price = 1").is_err());
+        assert!(SubscriptionFilter::parse(&"x".repeat(MAX_FILTER_LENGTH + 1)).is_err());
+        assert_eq!(
+            SubscriptionFilter::parse(&format!("{}a == 1{}", "(".repeat(20), ")".repeat(20))).unwrap_err(),
+            FilterError::TooDeep
+        );
+    }
+
+    #[test]
+    fn test_non_json_payloads_never_match() {
+        let filter = SubscriptionFilter::parse("price > 1").unwrap();
+        assert!(filter.matches_payload(&MessagePayload::from(r#"{"price":2}"#)));
+        assert!(!filter.matches_payload(&MessagePayload::from("price=2")));
+        assert!(!filter.matches_payload(&MessagePayload::from(vec![0x7b, 0x7d])));
+    }
+}
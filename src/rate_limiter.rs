@@ -0,0 +1,163 @@
+use crate::actix::Actor as ActixActor;
+use crate::actix::ActorContext;
+use crate::actix_web_actors::ws::CloseCode;
+use crate::actix_web_actors::ws::CloseReason;
+use crate::actix_web_actors::ws::WebsocketContext;
+use crate::common_types::CommonResponse;
+use crate::common_types::JsonSerializable;
+use crate::warn;
+use std::time::Instant;
+
+const RATE_LIMITED_MESSAGE: &str = "Rate limit exceeded";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimitAction {
+    /// Reply with a `CommonResponse` error frame instead of processing the message
+    ErrorFrame,
+    /// Silently discard the message
+    Drop,
+    /// Discard the message and close with `1008 Policy Violation` after `max_violations` violations
+    Disconnect { max_violations: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Messages accepted back to back before the sustained rate applies
+    pub burst: u32,
+    pub sustained_per_second: f64,
+    pub action: RateLimitAction,
+}
+
+#[derive(Debug, PartialEq)]
+enum RateLimitVerdict {
+    Allow,
+    Reject,
+    Disconnect,
+}
+
+/// Token bucket of one connection, a disabled limiter admits everything
+pub(crate) struct RateLimiter {
+    rate_limit: Option<RateLimit>,
+    tokens: f64,
+    last_refill: Instant,
+    violations: usize,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate_limit: Option<RateLimit>) -> Self {
+        Self {
+            rate_limit,
+            tokens: rate_limit.map(|rate_limit| f64::from(rate_limit.burst)).unwrap_or(0.0),
+            last_refill: Instant::now(),
+            violations: 0,
+        }
+    }
+
+    /// Returns whether the message may be processed, otherwise the configured action is applied
+    pub(crate) fn admit<A>(&mut self, context: &mut WebsocketContext<A>) -> bool
+    where
+        A: ActixActor<Context = WebsocketContext<A>>,
+    {
+        let rate_limit = match self.rate_limit {
+            Some(rate_limit) => rate_limit,
+            None => return true,
+        };
+        match self.check(Instant::now()) {
+            RateLimitVerdict::Allow => true,
+            RateLimitVerdict::Reject => {
+                if let RateLimitAction::ErrorFrame = rate_limit.action {
+                    let mut response = CommonResponse::default();
+                    response.error.push(RATE_LIMITED_MESSAGE.to_owned());
+                    context.text(response.to_json());
+                }
+                false
+            }
+            RateLimitVerdict::Disconnect => {
+                warn!("Disconnecting client after {} rate limit violations", self.violations);
+                context.close(Some(CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some(RATE_LIMITED_MESSAGE.to_owned()),
+                }));
+                context.stop();
+                false
+            }
+        }
+    }
+
+    fn check(&mut self, now: Instant) -> RateLimitVerdict {
+        let rate_limit = match self.rate_limit {
+            Some(rate_limit) => rate_limit,
+            None => return RateLimitVerdict::Allow,
+        };
+        let elapsed = now.duration_since(self.last_refill);
+        let refill = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + refill * rate_limit.sustained_per_second).min(f64::from(rate_limit.burst));
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return RateLimitVerdict::Allow;
+        }
+        self.violations += 1;
+        match rate_limit.action {
+            RateLimitAction::Disconnect { max_violations } if self.violations >= max_violations => {
+                RateLimitVerdict::Disconnect
+            }
+            _ => RateLimitVerdict::Reject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(burst: u32, sustained_per_second: f64, action: RateLimitAction) -> RateLimiter {
+        RateLimiter::new(Some(RateLimit {
+            burst,
+            sustained_per_second,
+            action,
+        }))
+    }
+
+    #[test]
+    fn test_burst_then_sustained_rate() {
+        let mut limiter = limiter(3, 2.0, RateLimitAction::Drop);
+        let start = limiter.last_refill;
+        for _ in 0..3 {
+            assert_eq!(limiter.check(start), RateLimitVerdict::Allow);
+        }
+        assert_eq!(limiter.check(start), RateLimitVerdict::Reject);
+        assert_eq!(
+            limiter.check(start + Duration::from_millis(250)),
+            RateLimitVerdict::Reject
+        );
+        assert_eq!(
+            limiter.check(start + Duration::from_millis(500)),
+            RateLimitVerdict::Allow
+        );
+        let idle = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(idle), RateLimitVerdict::Allow);
+        }
+        assert_eq!(limiter.check(idle), RateLimitVerdict::Reject);
+    }
+
+    #[test]
+    fn test_disconnect_after_max_violations() {
+        let mut limiter = limiter(1, 1.0, RateLimitAction::Disconnect { max_violations: 2 });
+        let start = limiter.last_refill;
+        assert_eq!(limiter.check(start), RateLimitVerdict::Allow);
+        assert_eq!(limiter.check(start), RateLimitVerdict::Reject);
+        assert_eq!(limiter.check(start), RateLimitVerdict::Disconnect);
+    }
+
+    #[test]
+    fn test_disabled_limiter_allows_everything() {
+        let mut limiter = RateLimiter::new(None);
+        let start = limiter.last_refill;
+        for _ in 0..1000 {
+            assert_eq!(limiter.check(start), RateLimitVerdict::Allow);
+        }
+    }
+}
@@ -1,7 +1,16 @@
+use crate::actix::fut::ok as actor_ok;
+use crate::actix::fut::WrapFuture;
+use crate::actix::fut::WrapStream;
 use crate::actix::Actor as ActixActor;
 use crate::actix::ActorContext;
+use crate::actix::ActorFuture;
+use crate::actix::ActorStream;
+use crate::actix::AsyncContext;
+use crate::actix::Handler;
 use crate::actix::Running;
+use crate::actix::SpawnHandle;
 use crate::actix::StreamHandler;
+use crate::actix::System as ActixSystem;
 use crate::actix_web::middleware;
 use crate::actix_web::web;
 use crate::actix_web::web::Data as ActixData;
@@ -12,46 +21,189 @@ use crate::actix_web::HttpRequest;
 use crate::actix_web::HttpResponse;
 use crate::actix_web::HttpServer as ActixHttpServer;
 use crate::actix_web_actors::ws::start as ws_start;
+use crate::actix_web_actors::ws::CloseCode;
 use crate::actix_web_actors::ws::Message as WsMessage;
 use crate::actix_web_actors::ws::ProtocolError as WsProtocolError;
 use crate::actix_web_actors::ws::WebsocketContext;
 use crate::auth::AuthMode;
 use crate::common_types::CommonResponse;
+use crate::common_types::JsonSerializable;
+use crate::common_types::MessagePayload;
+use crate::connection_context::ConnectionContext;
 use crate::debug;
+use crate::error;
+use crate::futures::future::err;
 use crate::futures::future::ok;
 use crate::futures::prelude::*;
+use crate::handler_error::call_handler;
+use crate::handler_error::handler_panicked;
+use crate::handler_error::HandlerError;
 use crate::info;
+use crate::json_rpc::JsonRpcRegistry;
+use crate::message_router::MessageRouter;
+use crate::rate_limiter::RateLimit;
+use crate::rate_limiter::RateLimiter;
+use crate::read_gate::GatedPayload;
+use crate::read_gate::ReadGate;
+use crate::request_pipeline::Admission;
+use crate::request_pipeline::InFlightGuard;
+use crate::request_pipeline::InFlightLimit;
+use crate::request_pipeline::PipelinedRequest;
+use crate::request_pipeline::RequestPipeline;
+use crate::request_pipeline::ResponseOrdering;
+use crate::service_handle::notify_start_failed;
+use crate::service_handle::start_service;
+use crate::service_handle::ConnectedClients;
+use crate::service_handle::GoingAway;
+use crate::service_handle::ServiceHandle;
+use crate::service_handle::ServiceHandleSlot;
+use crate::service_handle::StartedNotifier;
+use crate::typed_handler::TypedHandler;
+use crate::warn;
 use crate::ACTOR_MAILBOX_CAPACITY;
 use crate::NOTFOUND_MESSAGE;
+use serde::Deserialize;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::io::Result as IOResult;
+use std::panic::AssertUnwindSafe;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
-use std::time::Instant;
+
+const REQUEST_TIMEOUT_MESSAGE: &str = "Request timed out";
+const TOO_MANY_REQUESTS_MESSAGE: &str = "Too many requests";
+
+pub type ReactiveResponseFuture = Box<dyn Future<Item = Option<MessagePayload>, Error = HandlerError>>;
+pub type ReactiveResponseStream = Box<dyn Stream<Item = MessagePayload, Error = ()>>;
+pub type SyncMessageHandler =
+    Arc<&'static (dyn Fn(&mut ConnectionContext, MessagePayload) -> Option<MessagePayload> + Sync + Send)>;
+pub type FallibleMessageHandler = Arc<
+    &'static (dyn Fn(&mut ConnectionContext, MessagePayload) -> Result<Option<MessagePayload>, HandlerError>
+                  + Sync
+                  + Send),
+>;
+pub type AsyncMessageHandler =
+    Arc<&'static (dyn Fn(&mut ConnectionContext, MessagePayload) -> ReactiveResponseFuture + Sync + Send)>;
+pub type StreamMessageHandler =
+    Arc<&'static (dyn Fn(&mut ConnectionContext, MessagePayload) -> ReactiveResponseStream + Sync + Send)>;
+
+#[derive(Clone)]
+pub enum MessageHandler {
+    /// Called inline on the connection's arbiter thread
+    Sync(SyncMessageHandler),
+    /// Same as `Sync`, an error is answered with a `CommonResponse` error carrying the request `id`
+    Fallible(FallibleMessageHandler),
+    /** The returned future is polled on the connection's arbiter, so blocking work must still be
+    offloaded, e.g. with `actix_web::web::block`. A failed future is answered like a `Fallible` error.
+    The client cancels its request with a cancel control frame, like a stream */
+    Async(AsyncMessageHandler),
+    /// Requests are dispatched to the registered JSON-RPC 2.0 methods
+    JsonRpc(Arc<JsonRpcRegistry>),
+    /// Requests are dispatched to named handlers on a field of the message
+    Router(Arc<MessageRouter>),
+    /// Requests and responses are deserialized and serialized by the handler's `JsonSerializable` types
+    Typed(Arc<TypedHandler>),
+    /** Every message of the returned stream is written as soon as it is produced. The client cancels a stream
+    with `{"ws_control":"cancel","id":...}` matching the `id` of its request, or every running stream with
+    `{"ws_control":"cancel","all":true}`. Streams also stop when the client disconnects */
+    Stream(StreamMessageHandler),
+}
 
 pub struct ReactiveWebsocketConfig {
     pub binding_url: String,
     pub binding_path: String,
     pub max_clients: usize,
-    pub rapid_request_limit: Option<Duration>,
-    pub message_handler: Arc<&'static (dyn Fn(String) -> Option<String> + Sync + Send)>,
+    pub rate_limit: Option<RateLimit>,
+    pub message_handler: MessageHandler,
+    /** Extra paths served by the same server, each with its own handler set */
+    pub mounted_handlers: Vec<(String, MessageHandler)>,
+    /** Async handlers running at once per connection, further requests wait in arrival order */
+    pub max_concurrent_requests: usize,
+    pub response_ordering: ResponseOrdering,
+    /** Async requests still running after this long are dropped and answered with a `CommonResponse` error
+    carrying their `id`, sync handlers run inline and cannot time out */
+    pub request_timeout: Option<Duration>,
+    /** Caps the async requests and streams of one connection, sync handlers never have requests in flight */
+    pub in_flight_limit: Option<InFlightLimit>,
+    /** Answers a panicking handler with a `CommonResponse` error, or a panicking JSON-RPC method with an internal
+    error, and keeps the connection open. Needs `panic = 'unwind'`, with `panic = 'abort'` a handler panic still
+    ends the process */
+    pub catch_handler_panics: bool,
     pub auth: AuthMode,
 }
 
 pub struct ReactiveWebsocketState {
     pub active_clients: AtomicUsize,
     pub rejection_counter: AtomicUsize,
+    /// Times a connection stopped reading after reaching its in-flight limit
+    pub paused_reads_counter: AtomicUsize,
+    /// Requests rejected because their connection was at its in-flight limit
+    pub too_many_requests_counter: AtomicUsize,
     pub config: ReactiveWebsocketConfig,
+    connected_clients: ConnectedClients,
+    service_handle: ServiceHandleSlot,
 }
 
 pub(crate) struct ReactiveActor {
-    rapid_request_rejection_enabled: bool,
-    last_request_stopwatch: Instant,
-    rapid_request_limit: Duration,
+    connection: ConnectionContext,
+    connected_clients: &'static ConnectedClients,
+    paused_reads_counter: &'static AtomicUsize,
+    too_many_requests_counter: &'static AtomicUsize,
+    rate_limiter: RateLimiter,
     client_closed_callback: Box<dyn Fn()>,
-    message_handler: Arc<&'static (dyn Fn(String) -> Option<String> + Sync + Send)>,
+    message_handler: MessageHandler,
+    request_pipeline: RequestPipeline,
+    request_timeout: Option<Duration>,
+    catch_handler_panics: bool,
+    running_requests: HashMap<u64, RunningRequest>,
+    running_streams: HashMap<u64, RunningStream>,
+    next_stream_key: u64,
+    in_flight_guard: InFlightGuard,
+    read_gate: Arc<ReadGate>,
+}
+
+struct RunningRequest {
+    request_id: Option<Value>,
+    handle: SpawnHandle,
+    timeout: Option<SpawnHandle>,
+}
+
+struct RunningStream {
+    request_id: Option<Value>,
+    handle: SpawnHandle,
+}
+
+/// Control frames live under the reserved `ws_control` key, so no request of the handler is mistaken for one
+#[derive(Deserialize)]
+struct RequestControl {
+    #[serde(default)]
+    ws_control: Option<String>,
+    #[serde(default)]
+    id: Option<Value>,
+    #[serde(default)]
+    all: bool,
+}
+
+/// Requests a cancel control frame applies to
+#[derive(Debug, PartialEq)]
+enum CancelScope {
+    Request(Value),
+    /// Only with an explicit `"all":true`
+    All,
+    /// Neither `id` nor `all`, nothing is cancelled
+    Nothing,
+}
+
+impl CancelScope {
+    fn matches(&self, request_id: &Option<Value>) -> bool {
+        match self {
+            CancelScope::Request(id) => request_id.as_ref() == Some(id),
+            CancelScope::All => true,
+            CancelScope::Nothing => false,
+        }
+    }
 }
 
 impl ReactiveWebsocketState {
@@ -59,23 +211,45 @@ impl ReactiveWebsocketState {
         Self {
             active_clients: AtomicUsize::new(0),
             rejection_counter: AtomicUsize::new(0),
+            paused_reads_counter: AtomicUsize::new(0),
+            too_many_requests_counter: AtomicUsize::new(0),
             config,
+            connected_clients: ConnectedClients::default(),
+            service_handle: ServiceHandleSlot::default(),
         }
     }
+
+    /// Available once the server is listening, `start_reactive_websocket_service` also returns it
+    pub fn service_handle(&self) -> Option<ServiceHandle> {
+        self.service_handle.get()
+    }
 }
 
 impl ReactiveActor {
-    fn new(config: &'static ReactiveWebsocketConfig, client_closed_callback: Box<dyn Fn()>) -> Self {
+    fn new(
+        state: &'static ReactiveWebsocketState,
+        connection: ConnectionContext,
+        message_handler: MessageHandler,
+        read_gate: Arc<ReadGate>,
+        client_closed_callback: Box<dyn Fn()>,
+    ) -> Self {
+        let config = &state.config;
         Self {
-            rapid_request_rejection_enabled: config.rapid_request_limit.is_none(),
-            last_request_stopwatch: Instant::now(),
-            rapid_request_limit: if config.rapid_request_limit.is_none() {
-                Duration::from_secs(0)
-            } else {
-                config.rapid_request_limit.unwrap()
-            },
+            connection,
+            connected_clients: &state.connected_clients,
+            paused_reads_counter: &state.paused_reads_counter,
+            too_many_requests_counter: &state.too_many_requests_counter,
+            rate_limiter: RateLimiter::new(config.rate_limit),
             client_closed_callback,
-            message_handler: config.message_handler.clone(),
+            message_handler,
+            request_pipeline: RequestPipeline::new(config.max_concurrent_requests, config.response_ordering),
+            request_timeout: config.request_timeout,
+            catch_handler_panics: config.catch_handler_panics,
+            running_requests: HashMap::new(),
+            running_streams: HashMap::new(),
+            next_stream_key: 0,
+            in_flight_guard: InFlightGuard::new(config.in_flight_limit),
+            read_gate,
         }
     }
 }
@@ -85,35 +259,335 @@ impl ActixActor for ReactiveActor {
 
     fn started(&mut self, context: &mut Self::Context) {
         context.set_mailbox_capacity(ACTOR_MAILBOX_CAPACITY);
+        self.connected_clients
+            .register(self.connection.connection_id(), context.address().recipient());
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.connected_clients.unregister(&self.connection.connection_id());
         (*self.client_closed_callback)();
         Running::Stop
     }
 }
 
+impl Handler<GoingAway> for ReactiveActor {
+    type Result = ();
+
+    fn handle(&mut self, _: GoingAway, context: &mut Self::Context) {
+        context.close(Some(CloseCode::Away.into()));
+        context.stop();
+    }
+}
+
 impl StreamHandler<WsMessage, WsProtocolError> for ReactiveActor {
     fn handle(&mut self, payload: WsMessage, context: &mut Self::Context) {
-        if self.rapid_request_rejection_enabled {
-            if self.last_request_stopwatch.elapsed() < self.rapid_request_limit {
-                context.stop();
-                return;
-            }
-            self.last_request_stopwatch = Instant::now();
+        if let WsMessage::Close(_) = payload {
+            context.stop();
+            return;
+        }
+        if !self.rate_limiter.admit(context) {
+            return;
         }
         match payload {
-            WsMessage::Close(_) => context.stop(),
             WsMessage::Ping(ping_payload) => context.pong(&ping_payload),
-            WsMessage::Text(text) => {
-                let handler_clone = self.message_handler.clone();
-                if let Some(response_string) = handler_clone(text) {
-                    context.text(response_string)
+            WsMessage::Text(text) => self.handle_payload(MessagePayload::Text(text), context),
+            WsMessage::Binary(bytes) => self.handle_payload(MessagePayload::Binary(bytes), context),
+            _ => (),
+        }
+    }
+}
+
+impl ReactiveActor {
+    fn handle_payload(&mut self, payload: MessagePayload, context: &mut <Self as ActixActor>::Context) {
+        let streaming = match &self.message_handler {
+            MessageHandler::Async(_) => false,
+            MessageHandler::Stream(_) => true,
+            _ => return self.handle_inline(payload, context),
+        };
+        let (cancel, request_id) = parse_request_control(&payload);
+        if let Some(cancel_scope) = cancel {
+            if streaming {
+                self.cancel_streams(cancel_scope, context);
+            } else {
+                self.cancel_requests(cancel_scope, context);
+            }
+            return;
+        }
+        match self.in_flight_guard.admit(self.in_flight(), payload) {
+            Admission::Start(payload) => self.start_payload(payload, context),
+            Admission::Held => self.update_read_gate(),
+            Admission::Rejected => {
+                self.too_many_requests_counter.fetch_add(1, Ordering::Relaxed);
+                context.text(request_error(TOO_MANY_REQUESTS_MESSAGE, request_id).to_json());
+            }
+        }
+    }
+
+    /// Starts an admitted request of an async or stream handler
+    fn start_payload(&mut self, payload: MessagePayload, context: &mut <Self as ActixActor>::Context) {
+        match &self.message_handler {
+            MessageHandler::Async(_) => {
+                if let Some(request) = self.request_pipeline.admit(payload) {
+                    self.start_request(request, context);
                 }
+                self.update_read_gate();
+            }
+            MessageHandler::Stream(message_handler) => {
+                let message_handler = message_handler.clone();
+                self.start_stream(message_handler, payload, context);
             }
             _ => (),
         }
     }
+
+    /// Starts the held requests the connection has room for once some of its requests are done
+    fn start_held(&mut self, context: &mut <Self as ActixActor>::Context) {
+        while let Some(payload) = self.in_flight_guard.release(self.in_flight()) {
+            self.start_payload(payload, context);
+        }
+        self.update_read_gate();
+    }
+
+    fn handle_inline(&mut self, payload: MessagePayload, context: &mut <Self as ActixActor>::Context) {
+        let catch_panics = self.catch_handler_panics;
+        let request_id = match &self.message_handler {
+            MessageHandler::Fallible(_) => parse_request_control(&payload).1,
+            _ if catch_panics => parse_request_control(&payload).1,
+            _ => None,
+        };
+        let connection = &mut self.connection;
+        let response = match &self.message_handler {
+            MessageHandler::Sync(message_handler) => {
+                call_handler(catch_panics, || message_handler(connection, payload))
+            }
+            MessageHandler::Fallible(message_handler) => {
+                call_handler(catch_panics, || message_handler(connection, payload)).and_then(|response| response)
+            }
+            // Panics of JSON-RPC methods are answered by the registry, with the id of the request
+            MessageHandler::JsonRpc(json_rpc_registry) => {
+                Ok(json_rpc_registry.handle_with(catch_panics, connection, &payload))
+            }
+            MessageHandler::Router(message_router) => {
+                call_handler(catch_panics, || message_router.handle(connection, payload))
+            }
+            MessageHandler::Typed(typed_handler) => {
+                call_handler(catch_panics, || Some(typed_handler.handle(connection, &payload)))
+            }
+            MessageHandler::Async(_) | MessageHandler::Stream(_) => return,
+        };
+        let response = response.unwrap_or_else(|handler_error| Some(handler_error_payload(handler_error, request_id)));
+        if let Some(response_payload) = response {
+            context.write_raw(response_payload.into_ws_message())
+        }
+    }
+
+    fn start_stream(
+        &mut self,
+        message_handler: StreamMessageHandler,
+        payload: MessagePayload,
+        context: &mut <Self as ActixActor>::Context,
+    ) {
+        let (_, request_id) = parse_request_control(&payload);
+        let stream_key = self.next_stream_key;
+        self.next_stream_key += 1;
+        let catch_panics = self.catch_handler_panics;
+        let connection = &mut self.connection;
+        let response_stream: ReactiveResponseStream =
+            match call_handler(catch_panics, || message_handler(connection, payload)) {
+                Ok(response_stream) if catch_panics => {
+                    let panicked_request_id = request_id.clone();
+                    Box::new(
+                        AssertUnwindSafe(response_stream)
+                            .catch_unwind()
+                            .then(move |response| match response {
+                                Ok(response) => response,
+                                Err(panic) => Ok(handler_error_payload(
+                                    handler_panicked(&*panic),
+                                    panicked_request_id.clone(),
+                                )),
+                            }),
+                    )
+                }
+                Ok(response_stream) => response_stream,
+                Err(handler_error) => {
+                    let response_payload = handler_error_payload(handler_error, request_id);
+                    context.write_raw(response_payload.into_ws_message());
+                    return;
+                }
+            };
+        let response_stream = response_stream
+            .into_actor(self)
+            .map(|response_payload, _, context| context.write_raw(response_payload.into_ws_message()))
+            .finish()
+            .then(move |_, actor, context| {
+                actor.running_streams.remove(&stream_key);
+                actor.start_held(context);
+                actor_ok(())
+            });
+        let handle = context.spawn(response_stream);
+        self.running_streams
+            .insert(stream_key, RunningStream { request_id, handle });
+        self.update_read_gate();
+    }
+
+    fn cancel_streams(&mut self, cancel_scope: CancelScope, context: &mut <Self as ActixActor>::Context) {
+        let mut cancelled_streams = 0;
+        self.running_streams.retain(|_, running_stream| {
+            let cancelled = cancel_scope.matches(&running_stream.request_id);
+            if cancelled {
+                context.cancel_future(running_stream.handle);
+                cancelled_streams += 1;
+            }
+            !cancelled
+        });
+        cancelled_streams += self
+            .in_flight_guard
+            .cancel_held(|payload| cancel_scope.matches(&parse_request_control(payload).1));
+        self.start_held(context);
+        context.text(cancel_response(cancelled_streams).to_json());
+    }
+
+    fn in_flight(&self) -> usize {
+        self.request_pipeline.in_flight() + self.running_streams.len()
+    }
+
+    /// In pause mode, stops reading while the connection is at its limit or holds requests, resumes once it is below
+    fn update_read_gate(&mut self) {
+        if !self.in_flight_guard.pauses_reading(self.in_flight()) {
+            self.read_gate.resume();
+        } else if !self.read_gate.is_paused() {
+            self.read_gate.pause();
+            self.paused_reads_counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn start_request(&mut self, (pipeline_id, payload): PipelinedRequest, context: &mut <Self as ActixActor>::Context) {
+        let message_handler = match &self.message_handler {
+            MessageHandler::Async(message_handler) => message_handler.clone(),
+            _ => return,
+        };
+        let (_, request_id) = parse_request_control(&payload);
+        let failed_request_id = request_id.clone();
+        let client_id = self.connection.connection_id();
+        let catch_panics = self.catch_handler_panics;
+        let connection = &mut self.connection;
+        let response_future: ReactiveResponseFuture =
+            match call_handler(catch_panics, || message_handler(connection, payload)) {
+                Ok(response_future) if catch_panics => Box::new(
+                    AssertUnwindSafe(response_future)
+                        .catch_unwind()
+                        .then(|response| response.unwrap_or_else(|panic| Err(handler_panicked(&*panic)))),
+                ),
+                Ok(response_future) => response_future,
+                Err(handler_error) => Box::new(err(handler_error)),
+            };
+        let response_future = response_future
+            .then(move |response| {
+                Ok::<_, ()>(response.unwrap_or_else(|handler_error| {
+                    warn!(
+                        "Async handler failed request {} of client {}: {}",
+                        pipeline_id, client_id, handler_error
+                    );
+                    Some(handler_error_payload(handler_error, failed_request_id))
+                }))
+            })
+            .into_actor(self)
+            .map(move |response, actor, context| actor.finish_request(pipeline_id, response, context));
+        let handle = context.spawn(response_future);
+        let timeout = self.request_timeout.map(|request_timeout| {
+            context.run_later(request_timeout, move |actor, context| {
+                actor.expire_request(pipeline_id, context)
+            })
+        });
+        self.running_requests.insert(
+            pipeline_id,
+            RunningRequest {
+                request_id,
+                handle,
+                timeout,
+            },
+        );
+    }
+
+    fn finish_request(
+        &mut self,
+        pipeline_id: u64,
+        response: Option<MessagePayload>,
+        context: &mut <Self as ActixActor>::Context,
+    ) {
+        let running_request = match self.running_requests.remove(&pipeline_id) {
+            Some(running_request) => running_request,
+            None => return,
+        };
+        if let Some(timeout) = running_request.timeout {
+            context.cancel_future(timeout);
+        }
+        self.release_request(pipeline_id, response, context);
+    }
+
+    fn expire_request(&mut self, pipeline_id: u64, context: &mut <Self as ActixActor>::Context) {
+        let running_request = match self.running_requests.remove(&pipeline_id) {
+            Some(running_request) => running_request,
+            None => return,
+        };
+        context.cancel_future(running_request.handle);
+        warn!(
+            "Request {} of client {} timed out",
+            pipeline_id,
+            self.connection.connection_id()
+        );
+        let response = request_error(REQUEST_TIMEOUT_MESSAGE, running_request.request_id);
+        self.release_request(pipeline_id, Some(MessagePayload::Text(response.to_json())), context);
+    }
+
+    /// Cancels the running and queued requests matching `cancel_scope`
+    fn cancel_requests(&mut self, cancel_scope: CancelScope, context: &mut <Self as ActixActor>::Context) {
+        let cancelled = |candidate: &Option<Value>| cancel_scope.matches(candidate);
+        let (mut cancelled_requests, ready_responses) = self
+            .request_pipeline
+            .cancel_queued(|payload| cancelled(&parse_request_control(payload).1));
+        cancelled_requests += self
+            .in_flight_guard
+            .cancel_held(|payload| cancelled(&parse_request_control(payload).1));
+        for response_payload in ready_responses {
+            context.write_raw(response_payload.into_ws_message());
+        }
+        let cancelled_ids = self
+            .running_requests
+            .iter()
+            .filter(|(_, running_request)| cancelled(&running_request.request_id))
+            .map(|(pipeline_id, _)| *pipeline_id)
+            .collect::<Vec<_>>();
+        for pipeline_id in cancelled_ids {
+            if let Some(running_request) = self.running_requests.remove(&pipeline_id) {
+                context.cancel_future(running_request.handle);
+                if let Some(timeout) = running_request.timeout {
+                    context.cancel_future(timeout);
+                }
+                cancelled_requests += 1;
+                self.release_request(pipeline_id, None, context);
+            }
+        }
+        self.start_held(context);
+        context.text(cancel_response(cancelled_requests).to_json());
+    }
+
+    /// Frees the pipeline slot of a request, writing the responses it unblocks and starting the next queued request
+    fn release_request(
+        &mut self,
+        pipeline_id: u64,
+        response: Option<MessagePayload>,
+        context: &mut <Self as ActixActor>::Context,
+    ) {
+        let (ready_responses, next_request) = self.request_pipeline.complete(pipeline_id, response);
+        for response_payload in ready_responses {
+            context.write_raw(response_payload.into_ws_message());
+        }
+        if let Some(next_request) = next_request {
+            self.start_request(next_request, context);
+        }
+        self.start_held(context);
+    }
 }
 
 fn reject_unmapped_handler(
@@ -139,14 +613,19 @@ fn ws_upgrader(
     shared_state: ActixData<Arc<&'static ReactiveWebsocketState>>,
     request: HttpRequest,
     stream: Payload,
+    message_handler: MessageHandler,
 ) -> Result<HttpResponse, HttpError> {
-    let ReactiveWebsocketState {
-        config, active_clients, ..
-    } = shared_state.get_ref().as_ref();
-    config.auth.validate(&request)?;
+    let state: &'static ReactiveWebsocketState = *shared_state.get_ref().as_ref();
+    let active_clients = &state.active_clients;
+    let claims = state.config.auth.validate(&request)?;
+    let connection = ConnectionContext::new(request.peer_addr(), request.headers().clone(), claims);
+    let read_gate = Arc::new(ReadGate::default());
     let upgrade_result = ws_start(
         ReactiveActor::new(
-            &config,
+            state,
+            connection,
+            message_handler,
+            read_gate.clone(),
             Box::new(move || {
                 let active_clients = active_clients.fetch_sub(1, Ordering::Relaxed);
                 info!(
@@ -156,7 +635,7 @@ fn ws_upgrader(
             }),
         ),
         &request,
-        stream,
+        GatedPayload::new(stream, read_gate),
     );
     if upgrade_result.is_ok() {
         let active_clients = shared_state.active_clients.fetch_add(1, Ordering::Relaxed);
@@ -169,23 +648,164 @@ fn ws_upgrader(
 }
 
 pub fn run_reactive_websocket_service(state: Arc<&'static ReactiveWebsocketState>) -> IOResult<()> {
+    serve_reactive_websocket(state, None)
+}
+
+/// Runs the service on a thread of its own, returns once the server is listening
+pub fn start_reactive_websocket_service(state: Arc<&'static ReactiveWebsocketState>) -> IOResult<ServiceHandle> {
+    start_service("reactive-websocket", move |started| {
+        if let Err(run_error) = serve_reactive_websocket(state, Some(started)) {
+            error!("Reactive websocket service failed: {}", run_error);
+        }
+    })
+}
+
+fn serve_reactive_websocket(
+    state: Arc<&'static ReactiveWebsocketState>,
+    started: Option<StartedNotifier>,
+) -> IOResult<()> {
     let ReactiveWebsocketConfig {
         binding_url,
         binding_path,
         max_clients,
+        message_handler,
+        mounted_handlers,
         ..
     } = &state.config;
+    let handler_mounts = std::iter::once((binding_path.as_str(), message_handler.clone()))
+        .chain(
+            mounted_handlers
+                .iter()
+                .map(|(path, handler)| (path.as_str(), handler.clone())),
+        )
+        .collect::<Vec<_>>();
+    let connected_clients = &state.connected_clients;
+    let service_handle = &state.service_handle;
     let shared_data = ActixData::new(state);
-    ActixHttpServer::new(move || {
-        ActixApp::new()
+    let system = ActixSystem::new("reactive-websocket");
+    let bound = ActixHttpServer::new(move || {
+        let mut app = ActixApp::new()
             .register_data(shared_data.clone())
-            .wrap(middleware::Logger::default())
-            .service(web::resource(&binding_path).route(web::get().to(ws_upgrader)))
-            .default_service(web::route().to_async(reject_unmapped_handler))
+            .wrap(middleware::Logger::default());
+        for (path, message_handler) in handler_mounts.iter() {
+            let message_handler = message_handler.clone();
+            let upgrader = move |shared_state: ActixData<Arc<&'static ReactiveWebsocketState>>,
+                                 request: HttpRequest,
+                                 stream: Payload| {
+                ws_upgrader(shared_state, request, stream, message_handler.clone())
+            };
+            app = app.service(web::resource(*path).route(web::get().to(upgrader)));
+        }
+        app.default_service(web::route().to_async(reject_unmapped_handler))
     })
     .maxconn(*max_clients)
     .shutdown_timeout(1)
-    .bind(binding_url)
-    .unwrap()
-    .run()
+    .system_exit()
+    .bind(binding_url);
+    let server = match bound {
+        Ok(server) => server.start(),
+        Err(bind_error) => return Err(notify_start_failed(started, bind_error)),
+    };
+    let (stop_notifier, handle) = ServiceHandle::new(server, connected_clients);
+    service_handle.set(handle, started);
+    let run_result = system.run();
+    let _ = stop_notifier.send(());
+    run_result
+}
+
+/// Returns what the payload cancels when it is a cancel control frame, and the request `id` it carries
+fn parse_request_control(payload: &MessagePayload) -> (Option<CancelScope>, Option<Value>) {
+    let control = payload
+        .as_text()
+        .and_then(|text| serde_json::from_str::<RequestControl>(text).ok());
+    match control {
+        Some(RequestControl { ws_control, id, all }) => {
+            let cancel = if ws_control.as_ref().map(String::as_str) != Some("cancel") {
+                None
+            } else if all {
+                Some(CancelScope::All)
+            } else {
+                Some(id.clone().map_or(CancelScope::Nothing, CancelScope::Request))
+            };
+            (cancel, id)
+        }
+        None => (None, None),
+    }
+}
+
+fn request_id_text(request_id: Value) -> String {
+    match request_id {
+        Value::String(request_id) => request_id,
+        request_id => request_id.to_string(),
+    }
+}
+
+fn handler_error_payload(handler_error: HandlerError, request_id: Option<Value>) -> MessagePayload {
+    MessagePayload::Text(request_error(handler_error.message(), request_id).to_json())
+}
+
+/// Error response of a single request, carrying its `id` when it has one
+fn request_error(message: &str, request_id: Option<Value>) -> CommonResponse {
+    let mut response = CommonResponse::default();
+    response.error.push(message.to_owned());
+    if let Some(request_id) = request_id {
+        response.result.insert("id".to_owned(), request_id_text(request_id));
+    }
+    response
+}
+
+fn cancel_response(cancelled: usize) -> CommonResponse {
+    let mut response = CommonResponse::default();
+    response.result.insert("ws_control".to_owned(), "cancel".to_owned());
+    response.result.insert("cancelled".to_owned(), cancelled.to_string());
+    response
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_request_control_parsing() {
+        let control = |text: &str| parse_request_control(&MessagePayload::from(text));
+        assert_eq!(
+            control(r#"{"ws_control":"cancel","id":7}"#),
+            (Some(CancelScope::Request(Value::from(7))), Some(Value::from(7)))
+        );
+        assert_eq!(
+            control(r#"{"ws_control":"cancel","all":true}"#),
+            (Some(CancelScope::All), None)
+        );
+        assert_eq!(
+            control(r#"{"ws_control":"cancel"}"#),
+            (Some(CancelScope::Nothing), None)
+        );
+        assert_eq!(control(r#"{"op":"cancel","id":7}"#), (None, Some(Value::from(7))));
+        assert_eq!(
+            control(r#"{"method":"export_trades","id":"x1"}"#),
+            (None, Some(Value::from("x1")))
+        );
+        assert_eq!(control("export trades"), (None, None));
+        assert_ne!(
+            control(r#"{"ws_control":"cancel","id":"7"}"#).1,
+            control(r#"{"id":7}"#).1
+        );
+    }
+
+    #[test]
+    fn test_cancel_scope_matching() {
+        let id = Some(Value::from(7));
+        assert!(CancelScope::Request(Value::from(7)).matches(&id));
+        assert!(!CancelScope::Request(Value::from("7")).matches(&id));
+        assert!(!CancelScope::Request(Value::from(7)).matches(&None));
+        assert!(CancelScope::All.matches(&None));
+        assert!(!CancelScope::Nothing.matches(&id));
+        assert!(!CancelScope::Nothing.matches(&None));
+    }
+
+    #[test]
+    fn test_request_id_text() {
+        assert_eq!(request_id_text(Value::from("x1")), "x1");
+        assert_eq!(request_id_text(Value::from(7)), "7");
+    }
 }
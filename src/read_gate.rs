@@ -0,0 +1,80 @@
+use crate::futures::prelude::*;
+use crate::futures::task::AtomicTask;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Lets an actor stop and restart the reads of its own socket
+#[derive(Default)]
+pub(crate) struct ReadGate {
+    paused: AtomicBool,
+    reader: AtomicTask,
+}
+
+impl ReadGate {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn resume(&self) {
+        if self.paused.swap(false, Ordering::AcqRel) {
+            self.reader.notify();
+        }
+    }
+}
+
+/** Request payload that is not polled while its gate is paused, so the client is held back by TCP flow control.
+Frames already buffered by the websocket codec are still delivered, the actor holds them back itself */
+pub(crate) struct GatedPayload<S> {
+    stream: S,
+    gate: Arc<ReadGate>,
+}
+
+impl<S> GatedPayload<S> {
+    pub(crate) fn new(stream: S, gate: Arc<ReadGate>) -> Self {
+        Self { stream, gate }
+    }
+}
+
+impl<S: Stream> Stream for GatedPayload<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.gate.is_paused() {
+            self.gate.reader.register();
+            if self.gate.is_paused() {
+                return Ok(Async::NotReady);
+            }
+        }
+        self.stream.poll()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::futures::future::lazy;
+    use crate::futures::stream::iter_ok;
+
+    #[test]
+    fn test_paused_gate_holds_back_reads() {
+        let gate = Arc::new(ReadGate::default());
+        let mut payload = GatedPayload::new(iter_ok::<_, ()>(vec![1, 2]), gate.clone());
+        lazy(move || {
+            assert_eq!(payload.poll(), Ok(Async::Ready(Some(1))));
+            gate.pause();
+            assert_eq!(payload.poll(), Ok(Async::NotReady));
+            gate.resume();
+            assert!(!gate.is_paused());
+            assert_eq!(payload.poll(), Ok(Async::Ready(Some(2))));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ReplayResult<M> {
+    Replay(Vec<M>),
+    /// The requested sequence is no longer (or not yet) available, the client must resnapshot
+    Gap {
+        oldest_seq: u64,
+        last_seq: u64,
+    },
+}
+
+struct ReplayEntry<M> {
+    seq: u64,
+    topic: Option<String>,
+    message: M,
+}
+
+pub(crate) struct ReplayBuffer<M: Clone> {
+    capacity: usize,
+    last_seq: u64,
+    entries: VecDeque<ReplayEntry<M>>,
+}
+
+impl<M: Clone> ReplayBuffer<M> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            last_seq: 0,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub(crate) fn next_seq(&mut self) -> u64 {
+        self.last_seq += 1;
+        self.last_seq
+    }
+
+    pub(crate) fn record(&mut self, seq: u64, topic: Option<String>, message: M) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(ReplayEntry { seq, topic, message });
+    }
+
+    pub(crate) fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Messages after `seq` that `accept` returns true for, given their topic
+    pub(crate) fn replay_since<F>(&self, seq: u64, accept: F) -> ReplayResult<M>
+    where
+        F: Fn(Option<&str>, &M) -> bool,
+    {
+        let oldest_seq = self.entries.front().map(|entry| entry.seq).unwrap_or(self.last_seq + 1);
+        if !self.is_enabled() || seq > self.last_seq || seq + 1 < oldest_seq {
+            return ReplayResult::Gap {
+                oldest_seq,
+                last_seq: self.last_seq,
+            };
+        }
+        let messages = self
+            .entries
+            .iter()
+            .filter(|entry| entry.seq > seq)
+            .filter(|entry| accept(entry.topic.as_ref().map(String::as_str), &entry.message))
+            .map(|entry| entry.message.clone())
+            .collect();
+        ReplayResult::Replay(messages)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn filled_buffer(capacity: usize, count: u64) -> ReplayBuffer<u64> {
+        let mut buffer = ReplayBuffer::new(capacity);
+        for _ in 0..count {
+            let seq = buffer.next_seq();
+            let topic = if seq % 2 == 0 { Some("even".to_owned()) } else { None };
+            buffer.record(seq, topic, seq);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_replay_returns_messages_after_sequence() {
+        let buffer = filled_buffer(8, 5);
+        assert_eq!(buffer.replay_since(2, |_, _| true), ReplayResult::Replay(vec![3, 4, 5]));
+        assert_eq!(
+            buffer.replay_since(2, |topic, _| topic.is_none()),
+            ReplayResult::Replay(vec![3, 5])
+        );
+        assert_eq!(
+            buffer.replay_since(2, |topic, seq| topic == Some("even") || *seq < 4),
+            ReplayResult::Replay(vec![3, 4])
+        );
+        assert_eq!(buffer.replay_since(5, |_, _| true), ReplayResult::Replay(vec![]));
+    }
+
+    #[test]
+    fn test_replay_reports_gap_when_evicted_or_unknown() {
+        let buffer = filled_buffer(3, 6);
+        assert_eq!(buffer.replay_since(3, |_, _| true), ReplayResult::Replay(vec![4, 5, 6]));
+        assert_eq!(
+            buffer.replay_since(2, |_, _| true),
+            ReplayResult::Gap {
+                oldest_seq: 4,
+                last_seq: 6
+            }
+        );
+        assert_eq!(
+            buffer.replay_since(7, |_, _| true),
+            ReplayResult::Gap {
+                oldest_seq: 4,
+                last_seq: 6
+            }
+        );
+    }
+
+    #[test]
+    fn test_disabled_buffer_always_reports_gap() {
+        let buffer = filled_buffer(0, 3);
+        assert_eq!(
+            buffer.replay_since(3, |_, _| true),
+            ReplayResult::Gap {
+                oldest_seq: 4,
+                last_seq: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use crate::common_types::MessagePayload;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseOrdering {
+    /// Responses are written in the order their requests arrived
+    InOrder,
+    /// Responses are written as soon as their handler completes
+    AsCompleted,
+}
+
+impl Default for ResponseOrdering {
+    fn default() -> Self {
+        Self::InOrder
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InFlightLimitAction {
+    /** Stop reading from the socket until a request completes. Requests the websocket codec had already read past
+    the limit are held, unstarted, until the connection is below it */
+    PauseReading,
+    /// Answer further requests with a "Too many requests" `CommonResponse` error
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InFlightLimit {
+    /// Running and queued requests of one connection, streams included
+    pub max_requests: usize,
+    pub action: InFlightLimitAction,
+}
+
+pub(crate) type PipelinedRequest = (u64, MessagePayload);
+
+/// Tracks the requests of one connection, at most `max_concurrent_requests` run at once, the rest wait in arrival order
+pub(crate) struct RequestPipeline {
+    max_concurrent_requests: usize,
+    ordering: ResponseOrdering,
+    running_requests: usize,
+    queued_requests: VecDeque<PipelinedRequest>,
+    next_request_id: u64,
+    next_response_id: u64,
+    completed_responses: BTreeMap<u64, Option<MessagePayload>>,
+}
+
+impl RequestPipeline {
+    pub(crate) fn new(max_concurrent_requests: usize, ordering: ResponseOrdering) -> Self {
+        Self {
+            max_concurrent_requests: max_concurrent_requests.max(1),
+            ordering,
+            running_requests: 0,
+            queued_requests: VecDeque::new(),
+            next_request_id: 0,
+            next_response_id: 0,
+            completed_responses: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.running_requests + self.queued_requests.len()
+    }
+
+    /// Returns the request when it can start right away, otherwise it is queued
+    pub(crate) fn admit(&mut self, payload: MessagePayload) -> Option<PipelinedRequest> {
+        let request = (self.next_request_id, payload);
+        self.next_request_id += 1;
+        if self.running_requests < self.max_concurrent_requests {
+            self.running_requests += 1;
+            Some(request)
+        } else {
+            self.queued_requests.push_back(request);
+            None
+        }
+    }
+
+    /// Returns the responses that can be written now and the queued request that takes the freed slot
+    pub(crate) fn complete(
+        &mut self,
+        request_id: u64,
+        response: Option<MessagePayload>,
+    ) -> (Vec<MessagePayload>, Option<PipelinedRequest>) {
+        let ready_responses = match self.ordering {
+            ResponseOrdering::AsCompleted => response.into_iter().collect(),
+            ResponseOrdering::InOrder => {
+                self.completed_responses.insert(request_id, response);
+                self.release_responses()
+            }
+        };
+        let next_request = self.queued_requests.pop_front();
+        if next_request.is_none() {
+            self.running_requests -= 1;
+        }
+        (ready_responses, next_request)
+    }
+
+    /// Drops the queued requests matching `cancelled`, returns how many were dropped and the responses this unblocks
+    pub(crate) fn cancel_queued<F>(&mut self, cancelled: F) -> (usize, Vec<MessagePayload>)
+    where
+        F: Fn(&MessagePayload) -> bool,
+    {
+        let queued_requests = self.queued_requests.len();
+        let completed_responses = &mut self.completed_responses;
+        let ordering = self.ordering;
+        self.queued_requests.retain(|(request_id, payload)| {
+            let keep = !cancelled(payload);
+            if !keep && ordering == ResponseOrdering::InOrder {
+                completed_responses.insert(*request_id, None);
+            }
+            keep
+        });
+        (queued_requests - self.queued_requests.len(), self.release_responses())
+    }
+
+    fn release_responses(&mut self) -> Vec<MessagePayload> {
+        let mut ready_responses = Vec::new();
+        while let Some(response) = self.completed_responses.remove(&self.next_response_id) {
+            ready_responses.extend(response);
+            self.next_response_id += 1;
+        }
+        ready_responses
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Admission {
+    Start(MessagePayload),
+    Held,
+    Rejected,
+}
+
+/// Applies the in-flight limit of one connection to its requests as they are read
+pub(crate) struct InFlightGuard {
+    limit: Option<InFlightLimit>,
+    held_requests: VecDeque<MessagePayload>,
+}
+
+impl InFlightGuard {
+    pub(crate) fn new(limit: Option<InFlightLimit>) -> Self {
+        Self {
+            limit,
+            held_requests: VecDeque::new(),
+        }
+    }
+
+    /// At the limit a request is held in pause mode and rejected otherwise, it is also held behind earlier ones
+    pub(crate) fn admit(&mut self, in_flight: usize, payload: MessagePayload) -> Admission {
+        match self.limit {
+            Some(InFlightLimit { max_requests, action })
+                if in_flight >= max_requests || !self.held_requests.is_empty() =>
+            {
+                match action {
+                    InFlightLimitAction::PauseReading => {
+                        self.held_requests.push_back(payload);
+                        Admission::Held
+                    }
+                    InFlightLimitAction::Reject => Admission::Rejected,
+                }
+            }
+            _ => Admission::Start(payload),
+        }
+    }
+
+    /// Returns the next held request once the connection is below its limit
+    pub(crate) fn release(&mut self, in_flight: usize) -> Option<MessagePayload> {
+        match self.limit {
+            Some(InFlightLimit { max_requests, .. }) if in_flight < max_requests => self.held_requests.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Drops the held requests matching `cancelled`, returns how many were dropped
+    pub(crate) fn cancel_held<F>(&mut self, cancelled: F) -> usize
+    where
+        F: Fn(&MessagePayload) -> bool,
+    {
+        let held_requests = self.held_requests.len();
+        self.held_requests.retain(|payload| !cancelled(payload));
+        held_requests - self.held_requests.len()
+    }
+
+    /// In pause mode, reading stops while the connection is at its limit or still holds requests
+    pub(crate) fn pauses_reading(&self, in_flight: usize) -> bool {
+        match self.limit {
+            Some(InFlightLimit {
+                max_requests,
+                action: InFlightLimitAction::PauseReading,
+            }) => in_flight >= max_requests || !self.held_requests.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn texts(responses: Vec<MessagePayload>) -> Vec<String> {
+        responses
+            .into_iter()
+            .filter_map(|response| response.as_text().map(str::to_owned))
+            .collect()
+    }
+
+    fn admit_chunk(guard: &mut InFlightGuard, in_flight: &mut usize, chunk: &[&str]) -> Vec<Admission> {
+        chunk
+            .iter()
+            .map(|text| {
+                let admission = guard.admit(*in_flight, MessagePayload::from(*text));
+                if let Admission::Start(_) = admission {
+                    *in_flight += 1;
+                }
+                admission
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_paused_connection_holds_frames_read_past_the_limit() {
+        let mut guard = InFlightGuard::new(Some(InFlightLimit {
+            max_requests: 2,
+            action: InFlightLimitAction::PauseReading,
+        }));
+        let mut in_flight = 0;
+        let admissions = admit_chunk(&mut guard, &mut in_flight, &["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            admissions,
+            vec![
+                Admission::Start("a".into()),
+                Admission::Start("b".into()),
+                Admission::Held,
+                Admission::Held,
+                Admission::Held,
+            ]
+        );
+        assert!(guard.pauses_reading(in_flight));
+        assert_eq!(guard.release(in_flight), None);
+        in_flight -= 1;
+        assert_eq!(guard.release(in_flight), Some("c".into()));
+        in_flight += 1;
+        assert_eq!(guard.release(in_flight), None);
+        assert_eq!(guard.cancel_held(|payload| payload.as_text() == Some("d")), 1);
+        assert_eq!(guard.admit(0, "f".into()), Admission::Held);
+        assert_eq!(guard.release(0), Some("e".into()));
+        assert_eq!(guard.release(1), Some("f".into()));
+        assert!(!guard.pauses_reading(1));
+    }
+
+    #[test]
+    fn test_reject_mode_rejects_frames_read_past_the_limit() {
+        let mut guard = InFlightGuard::new(Some(InFlightLimit {
+            max_requests: 2,
+            action: InFlightLimitAction::Reject,
+        }));
+        let mut in_flight = 0;
+        let admissions = admit_chunk(&mut guard, &mut in_flight, &["a", "b", "c"]);
+        assert_eq!(admissions[2], Admission::Rejected);
+        assert_eq!(in_flight, 2);
+        assert!(!guard.pauses_reading(in_flight));
+    }
+
+    #[test]
+    fn test_requests_over_the_limit_are_queued() {
+        let mut pipeline = RequestPipeline::new(2, ResponseOrdering::AsCompleted);
+        assert_eq!(pipeline.admit("a".into()).map(|request| request.0), Some(0));
+        assert_eq!(pipeline.admit("b".into()).map(|request| request.0), Some(1));
+        assert_eq!(pipeline.admit("c".into()), None);
+        let (_, next_request) = pipeline.complete(1, None);
+        assert_eq!(next_request, Some((2, "c".into())));
+        assert_eq!(pipeline.complete(0, None).1, None);
+        assert_eq!(pipeline.complete(2, None).1, None);
+        assert!(pipeline.admit("d".into()).is_some());
+    }
+
+    #[test]
+    fn test_in_order_delivery_waits_for_earlier_responses() {
+        let mut pipeline = RequestPipeline::new(3, ResponseOrdering::InOrder);
+        for payload in &["a", "b", "c"] {
+            pipeline.admit((*payload).into());
+        }
+        assert!(pipeline.complete(2, Some("C".into())).0.is_empty());
+        assert!(pipeline.complete(1, None).0.is_empty());
+        assert_eq!(texts(pipeline.complete(0, Some("A".into())).0), vec!["A", "C"]);
+    }
+
+    #[test]
+    fn test_as_completed_delivery_writes_immediately() {
+        let mut pipeline = RequestPipeline::new(3, ResponseOrdering::AsCompleted);
+        pipeline.admit("a".into());
+        pipeline.admit("b".into());
+        assert_eq!(texts(pipeline.complete(1, Some("B".into())).0), vec!["B"]);
+        assert_eq!(texts(pipeline.complete(0, Some("A".into())).0), vec!["A"]);
+    }
+
+    #[test]
+    fn test_cancelled_queued_requests_do_not_block_ordering() {
+        let mut pipeline = RequestPipeline::new(1, ResponseOrdering::InOrder);
+        for payload in &["a", "b", "c"] {
+            pipeline.admit((*payload).into());
+        }
+        let (cancelled, ready_responses) = pipeline.cancel_queued(|payload| payload.as_text() == Some("b"));
+        assert_eq!((cancelled, ready_responses.len()), (1, 0));
+        let (ready_responses, next_request) = pipeline.complete(0, Some("A".into()));
+        assert_eq!(texts(ready_responses), vec!["A"]);
+        assert_eq!(next_request, Some((2, "c".into())));
+        assert_eq!(texts(pipeline.complete(2, Some("C".into())).0), vec!["C"]);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::actix::Message;
+use crate::actix::Recipient;
+use crate::actix_server::Server as ActixServer;
+use crate::crossbeam_channel::bounded;
+use crate::crossbeam_channel::Sender;
+use crate::futures::future::Shared;
+use crate::futures::sync::oneshot;
+use crate::futures::Future;
+use crate::info;
+use crate::uuid::Uuid;
+use std::collections::HashMap;
+use std::io::Error as IOError;
+use std::io::ErrorKind;
+use std::io::Result as IOResult;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread;
+
+pub(crate) type StopNotifier = oneshot::Sender<()>;
+pub(crate) type StartedNotifier = Sender<IOResult<ServiceHandle>>;
+
+#[derive(Message)]
+pub(crate) struct GoingAway;
+
+#[derive(Default)]
+pub(crate) struct ConnectedClients {
+    clients: Mutex<HashMap<Uuid, Recipient<GoingAway>>>,
+}
+
+impl ConnectedClients {
+    pub(crate) fn register(&self, client_id: Uuid, recipient: Recipient<GoingAway>) {
+        self.clients.lock().unwrap().insert(client_id, recipient);
+    }
+
+    pub(crate) fn unregister(&self, client_id: &Uuid) {
+        self.clients.lock().unwrap().remove(client_id);
+    }
+
+    fn close_all(&self) -> usize {
+        let clients = self.clients.lock().unwrap();
+        for recipient in clients.values() {
+            let _ = recipient.do_send(GoingAway);
+        }
+        clients.len()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ServiceHandleSlot {
+    handle: RwLock<Option<ServiceHandle>>,
+}
+
+impl ServiceHandleSlot {
+    pub(crate) fn get(&self) -> Option<ServiceHandle> {
+        self.handle.read().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, handle: ServiceHandle, started: Option<StartedNotifier>) {
+        *self.handle.write().unwrap() = Some(handle.clone());
+        if let Some(started) = started {
+            let _ = started.send(Ok(handle));
+        }
+    }
+}
+
+/// Hands the error of a service that could not start to whoever waits on `started`, and returns it
+pub(crate) fn notify_start_failed(started: Option<StartedNotifier>, error: IOError) -> IOError {
+    if let Some(started) = started {
+        let _ = started.send(Err(IOError::new(error.kind(), error.to_string())));
+    }
+    error
+}
+
+/** Runs `serve` on a thread of its own and returns the handle it publishes once the server is listening,
+or the error it reports when the server cannot start */
+pub(crate) fn start_service<F>(name: &str, serve: F) -> IOResult<ServiceHandle>
+where
+    F: FnOnce(StartedNotifier) + Send + 'static,
+{
+    let (started, handle) = bounded(1);
+    thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(move || serve(started))?;
+    handle
+        .recv()
+        .map_err(|_| IOError::new(ErrorKind::Other, format!("{} stopped before listening", name)))
+        .and_then(|started| started)
+}
+
+#[derive(Clone)]
+pub struct ServiceHandle {
+    server: ActixServer,
+    connected_clients: &'static ConnectedClients,
+    stopped: Shared<oneshot::Receiver<()>>,
+}
+
+impl ServiceHandle {
+    pub(crate) fn new(server: ActixServer, connected_clients: &'static ConnectedClients) -> (StopNotifier, Self) {
+        let (stop_notifier, stopped) = oneshot::channel();
+        let handle = Self {
+            server,
+            connected_clients,
+            stopped: stopped.shared(),
+        };
+        (stop_notifier, handle)
+    }
+
+    /// Stop accepting new connections, connected clients keep being served
+    pub fn pause(&self) -> impl Future<Item = (), Error = ()> {
+        self.server.pause()
+    }
+
+    pub fn resume(&self) -> impl Future<Item = (), Error = ()> {
+        self.server.resume()
+    }
+
+    /// A graceful stop closes every client with `1001 Going Away` before shutting the server down
+    pub fn stop(&self, graceful: bool) -> impl Future<Item = (), Error = ()> {
+        if graceful {
+            let closed_clients = self.connected_clients.close_all();
+            info!("Closing {} clients before graceful shutdown", closed_clients);
+        }
+        self.server.stop(graceful)
+    }
+
+    /// Resolves once the server is stopped and the helper threads of the service are joined
+    pub fn stopped(&self) -> impl Future<Item = (), Error = ()> {
+        self.stopped.clone().then(|_| Ok::<(), ()>(()))
+    }
+
+    pub fn join(&self) {
+        let _ = self.stopped().wait();
+    }
+}
@@ -0,0 +1,97 @@
+use crate::common_types::CommonResponse;
+use crate::common_types::JsonSerializable;
+use crate::common_types::MessagePayload;
+use crate::connection_context::ConnectionContext;
+
+type TypedCall = Box<dyn Fn(&mut ConnectionContext, &MessagePayload) -> MessagePayload + Send + Sync>;
+
+/** A reactive handler working on `JsonSerializable` types through `MessageHandler::Typed`.
+Messages that fail to deserialize into the request type are answered with a `CommonResponse` error */
+pub struct TypedHandler {
+    call: TypedCall,
+}
+
+impl TypedHandler {
+    pub fn new<Req, Resp, F>(handler: F) -> Self
+    where
+        Req: for<'de> JsonSerializable<'de> + 'static,
+        Resp: for<'de> JsonSerializable<'de> + 'static,
+        F: Fn(Req) -> Resp + Send + Sync + 'static,
+    {
+        Self::with_connection(move |_: &mut ConnectionContext, request: Req| handler(request))
+    }
+
+    /// Same as `new`, for handlers that need the claims or the session of the connection
+    pub fn with_connection<Req, Resp, F>(handler: F) -> Self
+    where
+        Req: for<'de> JsonSerializable<'de> + 'static,
+        Resp: for<'de> JsonSerializable<'de> + 'static,
+        F: Fn(&mut ConnectionContext, Req) -> Resp + Send + Sync + 'static,
+    {
+        let call = move |connection: &mut ConnectionContext, payload: &MessagePayload| {
+            let request = serde_json::from_slice::<Req>(payload.as_bytes());
+            match request {
+                Ok(request) => MessagePayload::Text(handler(connection, request).to_json()),
+                Err(error) => {
+                    let mut response = CommonResponse::default();
+                    response.error.push(format!("Invalid request: {}", error));
+                    MessagePayload::Text(response.to_json())
+                }
+            }
+        };
+        Self { call: Box::new(call) }
+    }
+
+    pub fn handle(&self, connection: &mut ConnectionContext, payload: &MessagePayload) -> MessagePayload {
+        (self.call)(connection, payload)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::actix_web::http::HeaderMap;
+    use serde::Deserialize;
+    use serde::Serialize;
+
+    #[derive(Deserialize, Serialize)]
+    struct QuoteRequest {
+        symbol: String,
+        depth: usize,
+    }
+
+    impl JsonSerializable<'_> for QuoteRequest {}
+
+    #[derive(Deserialize, Serialize)]
+    struct QuoteResponse {
+        symbol: String,
+        levels: Vec<u64>,
+    }
+
+    impl JsonSerializable<'_> for QuoteResponse {}
+
+    fn handle(request: &str) -> String {
+        let handler = TypedHandler::new(|request: QuoteRequest| QuoteResponse {
+            symbol: request.symbol,
+            levels: (0..request.depth as u64).collect(),
+        });
+        let mut connection = ConnectionContext::new(None, HeaderMap::new(), None);
+        let response = handler.handle(&mut connection, &MessagePayload::from(request));
+        response.as_text().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_typed_request_and_response() {
+        assert_eq!(
+            handle(r#"{"symbol":"BTC-USD","depth":2}"#),
+            r#"{"symbol":"BTC-USD","levels":[0,1]}"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_request_is_answered_with_common_response() {
+        let response = CommonResponse::from_json(&handle(r#"{"symbol":"BTC-USD"}"#)).unwrap();
+        assert_eq!(response.error.len(), 1);
+        assert!(response.error[0].starts_with("Invalid request: missing field `depth`"));
+    }
+}